@@ -0,0 +1,334 @@
+//! Command line arguments
+
+use crate::config::{self, ExtraLeaseDir};
+use crate::lease::LeaseFormat;
+use crate::monitor::DetectBy;
+use crate::route::RouteLookup;
+use crate::trigger::ArgsMode;
+use clap::{Parser, Subcommand};
+use std::time::Duration;
+
+// Command line arguments; options that are given override the config file
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Configuration file (TOML)
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Run in foreground
+    #[arg(short, long)]
+    pub foreground: bool,
+
+    /// PID file [default: /run/dhcpleasemon.pid on Linux, /var/run/dhcpleasemon.pid elsewhere]
+    #[arg(short, long)]
+    pub pid_file: Option<String>,
+
+    /// Root directory to chroot into before monitoring [default: /]
+    #[arg(short, long)]
+    pub root_dir: Option<String>,
+
+    /// User to run as after startup
+    #[arg(short, long)]
+    pub user: Option<String>,
+
+    /// Group to run as after startup (defaults to the user's primary group)
+    #[arg(short, long)]
+    pub group: Option<String>,
+
+    /// Directory with trigger scripts [default: /etc/dhcpleasemon]
+    #[arg(short, long)]
+    pub scripts_dir: Option<String>,
+
+    /// Name prefix for trigger scripts (IPv4) [default: lease_trigger_]
+    #[arg(long)]
+    pub trigger_script_prefix: Option<String>,
+
+    /// Name prefix for trigger scripts (IPv6), the same script as for IPv4 by default: branch on $DHCP_FAMILY [default: lease_trigger_]
+    #[arg(long)]
+    pub trigger_script_prefix_ipv6: Option<String>,
+
+    /// Script run once at startup, before monitoring begins
+    #[arg(long)]
+    pub init_script: Option<String>,
+
+    /// Abort startup if the init script fails
+    #[arg(long)]
+    pub init_script_required: bool,
+
+    /// Script run once on clean shutdown
+    #[arg(long)]
+    pub cleanup_script: Option<String>,
+
+    /// Abort startup if a configured interface has no trigger script
+    #[arg(long)]
+    pub require_scripts: bool,
+
+    /// Abort startup if a lease directory does not exist or cannot be read
+    #[arg(long)]
+    pub require_lease_dirs: bool,
+
+    /// Script run before the triggers of a scan that found lease changes
+    #[arg(long)]
+    pub pre_hook: Option<String>,
+
+    /// Skip the triggers of the scan if the pre-trigger hook fails
+    #[arg(long)]
+    pub pre_hook_required: bool,
+
+    /// Script run after the triggers of a scan that found lease changes
+    #[arg(long)]
+    pub post_hook: Option<String>,
+
+    /// Trigger script run for the interfaces (and families) that have no script of their own
+    #[arg(long)]
+    pub shared_script: Option<String>,
+
+    /// Directory monitored for lease changes [default: /var/db/dhcpleased]
+    #[arg(short, long)]
+    pub dhcp_lease_dir: Option<String>,
+
+    /// Directory monitored for IPv6 lease changes [default: /var/db/dhcp6leased]
+    #[arg(short = 'D', long)]
+    pub dhcp6_lease_dir: Option<String>,
+
+    /// Format of the lease files (the DHCP client writing them) [default: openbsd]
+    #[arg(long, value_enum)]
+    pub lease_format: Option<LeaseFormat>,
+
+    /// The dhcpcd binary run to read the leases (--lease-format dhcpcd), a bare name is looked up in PATH [default: /usr/local/sbin/dhcpcd on OpenBSD and FreeBSD, dhcpcd elsewhere]
+    #[arg(long)]
+    pub dhcpcd_path: Option<String>,
+
+    /// Lease file name template ({iface} is replaced with the interface name) [default: {iface}]
+    #[arg(long)]
+    pub lease_file_template: Option<String>,
+
+    /// Lease (IPv6) file name template ({iface} is replaced with the interface name) [default: {iface}]
+    #[arg(long)]
+    pub lease6_file_template: Option<String>,
+
+    /// Scan interval, e.g. 500ms, 2s, 5m (plain numbers are seconds) [default: 1s]
+    #[arg(short = 't', long, value_parser = config::parse_interval)]
+    pub interval: Option<Duration>,
+
+    /// Randomize every scan interval by up to this percentage of it [default: 0]
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub jitter: Option<u8>,
+
+    /// Start a scan every interval, counting the time the scans take, rather than sleeping the interval after each scan
+    #[arg(long)]
+    pub fixed_rate: bool,
+
+    /// Trigger a changed lease file only once it has not changed for this long, e.g. 2s [default: 0]
+    #[arg(long, value_parser = config::parse_duration)]
+    pub debounce: Option<Duration>,
+
+    /// Minimum time between the triggers of an interface, later changes are triggered once it passed, e.g. 1m [default: 0]
+    #[arg(long, value_parser = config::parse_duration)]
+    pub cooldown: Option<Duration>,
+
+    /// Run the trigger (DHCP_EVENT=expiring) this long before a lease expires without being renewed, ISC dhclient leases only, e.g. 5m [default: 0]
+    #[arg(long, value_parser = config::parse_duration)]
+    pub expiry_warning: Option<Duration>,
+
+    /// Fall back to the global default route if the interface has none
+    #[arg(long)]
+    pub route_fallback_global: bool,
+
+    /// How the default routes are found [default: sysctl on OpenBSD, netstat elsewhere]
+    #[arg(long, value_enum, alias = "route-from")]
+    pub route_lookup: Option<RouteLookup>,
+
+    /// The netstat binary run for the route lookup, a bare name is looked up in PATH [default: /usr/bin/netstat on OpenBSD and FreeBSD, /usr/sbin/netstat on macOS, netstat elsewhere]
+    #[arg(long)]
+    pub netstat_path: Option<String>,
+
+    /// Run the trigger scripts for the leases found at startup that are new or differ from the state file [default]
+    #[arg(long, conflicts_with = "no_trigger_on_startup")]
+    pub trigger_on_startup: bool,
+
+    /// Only record the leases found at startup (also the ones that differ from the state file), trigger on later changes
+    #[arg(long)]
+    pub no_trigger_on_startup: bool,
+
+    /// Keep the recorded leases of the interfaces that drop out of discovery (a pattern or --auto-discover changed on reload), and only record them when discovered again with the same lease
+    #[arg(long)]
+    pub no_trigger_on_rediscovery: bool,
+
+    /// Also run the trigger scripts when a lease is renewed unchanged
+    #[arg(long)]
+    pub trigger_on_renew: bool,
+
+    /// File to persist the last seen leases in, to trigger only on actual
+    /// changes after a restart
+    #[arg(long)]
+    pub state_file: Option<String>,
+
+    /// How lease file changes are detected [default: mtime]
+    #[arg(long, value_enum)]
+    pub detect_by: Option<DetectBy>,
+
+    /// Kill trigger scripts running longer than this many seconds, 0 to
+    /// wait forever [default: 0]
+    #[arg(long)]
+    pub script_timeout: Option<u64>,
+
+    /// Skip the remaining trigger scripts of a run once one fails
+    #[arg(long)]
+    pub stop_on_error: bool,
+
+    /// Run the IPv4 trigger script once, with the IPv6 variables too, when both leases of an interface change in one scan
+    #[arg(long)]
+    pub unified_trigger: bool,
+
+    /// Run a failing trigger script again up to this many times [default: 0]
+    #[arg(long)]
+    pub retry_count: Option<u32>,
+
+    /// Delay before retrying a failed trigger script, doubled for every further retry, e.g. 5s [default: 1s]
+    #[arg(long, value_parser = config::parse_duration)]
+    pub retry_delay: Option<Duration>,
+
+    /// JSON file with the current lease of every interface, rewritten after
+    /// every scan that changed any
+    #[arg(long)]
+    pub status_file: Option<String>,
+
+    /// Interfaces to monitor, names or shell globs matched against the lease
+    /// files (replace the ones from the config file)
+    #[arg(short, long)]
+    pub interfaces: Vec<String>,
+
+    /// Treat the interfaces as regexes instead of names or shell globs
+    #[arg(long)]
+    pub interface_regex: bool,
+
+    /// Also monitor every interface that has a lease file
+    #[arg(long)]
+    pub auto_discover: bool,
+
+    /// Interfaces never to monitor, names or globs, however they were selected (replace the ones from the config file)
+    #[arg(long)]
+    pub ignore: Vec<String>,
+
+    /// Also look for the lease files in this directory, <dir>:inet or <dir>:inet6 (replace the ones from the config file)
+    #[arg(long, value_name = "DIR:PROTO")]
+    pub extra_lease_dir: Vec<ExtraLeaseDir>,
+
+    /// Monitor IPv6 leases as well
+    #[arg(short = '6', long)]
+    pub ipv6: bool,
+
+    /// Also export dhclient-script style variables to trigger scripts
+    #[arg(long)]
+    pub dhclient_compat: bool,
+
+    /// Serve Prometheus metrics on http://<addr:port>/metrics
+    #[arg(long)]
+    pub metrics_listen: Option<String>,
+
+    /// POST the lease changes as JSON to this URL
+    #[arg(long)]
+    pub webhook_url: Option<String>,
+
+    /// Publish the lease changes to this MQTT broker (host[:port])
+    #[arg(long)]
+    pub mqtt_broker: Option<String>,
+
+    /// MQTT topic prefix, messages go to <prefix>/<iface>/ipv4 (or ipv6)
+    /// [default: dhcpleasemon]
+    #[arg(long)]
+    pub mqtt_topic_prefix: Option<String>,
+
+    /// MQTT user name
+    #[arg(long)]
+    pub mqtt_username: Option<String>,
+
+    /// MQTT password (better set in the config file)
+    #[arg(long)]
+    pub mqtt_password: Option<String>,
+
+    /// Also pass the lease change as JSON on the trigger scripts' stdin
+    #[arg(long)]
+    pub json_stdin: bool,
+
+    /// Pass the lease values as environment variables, positional arguments or both [default: env]
+    #[arg(long, value_enum)]
+    pub args_mode: Option<ArgsMode>,
+
+    /// Prefix of the environment variables passed to the scripts (IPv6: with a 6 appended) [default: DHCP_]
+    #[arg(long)]
+    pub env_prefix: Option<String>,
+
+    /// Separator of the list values in the environment (name servers, delegated prefixes), e.g. ',' or '\n' [default: ' ']
+    #[arg(long, value_parser = config::parse_separator)]
+    pub list_separator: Option<String>,
+
+    /// Shadow mode: detect and log lease changes but never run scripts
+    #[arg(long)]
+    pub shadow: bool,
+
+    /// Dry run: log the trigger scripts and their environment instead of running them (the init, cleanup and hook scripts are not run either)
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Do not restrict the daemon with pledge(2)/unveil(2) (OpenBSD only)
+    #[arg(long)]
+    pub no_sandbox: bool,
+
+    /// Log the triggers as structured entries to the systemd journal (Linux only)
+    #[arg(long)]
+    pub journal: bool,
+
+    /// Do not color the messages written to stderr by level
+    #[arg(long)]
+    pub no_color: bool,
+
+    /// Check the leases once, run the triggers and exit (exit status 0: no
+    /// change, 1: errors, 2: triggers run)
+    #[arg(long)]
+    pub oneshot: bool,
+
+    /// Print the effective configuration (defaults, config file and arguments combined) as a config file and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Check the setup (lease directories, trigger scripts, netstat, dhcpcd, PID file directory), report the problems and exit, nonzero if any would stop the daemon
+    #[arg(long)]
+    pub check: bool,
+
+    /// Unix domain socket to answer status requests on
+    #[arg(long, global = true)]
+    pub control_socket: Option<String>,
+
+    /// Number of recent lease changes kept per interface for the history query, 0 to keep none [default: 20]
+    #[arg(long)]
+    pub history_size: Option<usize>,
+
+    #[command(subcommand)]
+    pub action: Option<Action>,
+
+    /// Verbosity, repeat for more (-v info, -vv debug, -vvv trace)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbosity: u8,
+}
+
+/// Subcommands, without one the daemon is started
+#[derive(Subcommand, Debug, Clone)]
+pub enum Action {
+    /// Print the current leases known to the running daemon (needs its
+    /// --control-socket)
+    Status {
+        /// Only this interface
+        iface: Option<String>,
+    },
+    /// Print the recent lease changes seen by the running daemon (needs its
+    /// --control-socket)
+    History {
+        /// Only this interface
+        iface: Option<String>,
+    },
+}
+
+// EOF
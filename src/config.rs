@@ -0,0 +1,988 @@
+//! Daemon configuration: built-in defaults, overridden by the TOML config
+//! file (if any), overridden by the command line arguments
+
+use crate::args::Args;
+use crate::lease::{self, LeaseFormat};
+use crate::monitor::DetectBy;
+use crate::pidfile;
+use crate::route::{self, RouteLookup};
+use crate::trigger::{self, ArgsMode};
+use log::{warn, LevelFilter};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::env;
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub foreground: bool,
+    pub pid_file: String,
+    pub root_dir: String,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub scripts_dir: String,
+    pub trigger_script_prefix: String,
+    pub trigger_script_prefix_ipv6: String,
+    pub init_script: Option<String>,
+    pub init_script_required: bool,
+    pub cleanup_script: Option<String>,
+    pub require_scripts: bool,
+    pub require_lease_dirs: bool,
+    pub pre_hook: Option<String>,
+    pub pre_hook_required: bool,
+    pub post_hook: Option<String>,
+    pub shared_script: Option<String>,
+    pub dhcp_lease_dir: String,
+    pub dhcp6_lease_dir: String,
+    pub lease_format: LeaseFormat,
+    pub dhcpcd_path: String,
+    pub lease_file_template: String,
+    pub lease6_file_template: String,
+    #[serde(
+        deserialize_with = "deserialize_interval",
+        serialize_with = "serialize_duration"
+    )]
+    pub interval: Duration,
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub debounce: Duration,
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub cooldown: Duration,
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub expiry_warning: Duration,
+    #[serde(deserialize_with = "deserialize_percent")]
+    pub jitter: u8,
+    pub fixed_rate: bool,
+    pub route_fallback_global: bool,
+    pub route_lookup: RouteLookup,
+    pub netstat_path: String,
+    pub trigger_on_startup: bool,
+    pub no_trigger_on_rediscovery: bool,
+    pub trigger_on_renew: bool,
+    pub script_timeout: u64,
+    pub stop_on_error: bool,
+    pub unified_trigger: bool,
+    pub retry_count: u32,
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub retry_delay: Duration,
+    pub detect_by: DetectBy,
+    pub state_file: Option<String>,
+    pub status_file: Option<String>,
+    pub control_socket: Option<String>,
+    pub history_size: usize,
+    #[serde(rename = "interface")]
+    pub interfaces: Vec<InterfaceConfig>,
+    pub interface_regex: bool,
+    pub auto_discover: bool,
+    pub ignore: Vec<String>,
+    pub extra_lease_dir: Vec<ExtraLeaseDir>,
+    pub ipv6: bool,
+    pub dhclient_compat: bool,
+    pub json_stdin: bool,
+    pub args_mode: ArgsMode,
+    pub env_prefix: String,
+    #[serde(deserialize_with = "deserialize_separator")]
+    pub list_separator: String,
+    pub webhook_url: Option<String>,
+    pub metrics_listen: Option<String>,
+    pub mqtt_broker: Option<String>,
+    pub mqtt_topic_prefix: String,
+    pub mqtt_username: Option<String>,
+    pub mqtt_password: Option<String>,
+    pub shadow: bool,
+    pub dry_run: bool,
+    pub no_sandbox: bool,
+    pub journal: bool,
+    pub no_color: bool,
+    pub verbosity: u8,
+}
+
+/// A monitored interface (`[[interface]]`), settings fall back to the global ones
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct InterfaceConfig {
+    pub name: String,
+    /// Trigger script used instead of `<prefix><name>`, relative to the
+    /// scripts directory unless absolute
+    pub trigger_script: Option<String>,
+    pub trigger_script_ipv6: Option<String>,
+    pub trigger_script_prefix: Option<String>,
+    pub trigger_script_prefix_ipv6: Option<String>,
+    pub dhcp_lease_dir: Option<String>,
+    pub dhcp6_lease_dir: Option<String>,
+    /// Extra variables of the interface's trigger scripts, e.g.
+    /// `env = { ZONE = "dmz" }`; the daemon's own names are not overridden
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// The name compiled as a regex (`--interface-regex`), when loaded
+    #[serde(skip)]
+    pub regex: Option<Regex>,
+}
+
+/// An additional directory the lease files are looked for in
+/// (`--extra-lease-dir <dir>:<proto>`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct ExtraLeaseDir {
+    pub dir: String,
+    /// `inet` or `inet6`
+    pub proto: String,
+}
+
+impl FromStr for ExtraLeaseDir {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.rsplit_once(':') {
+            Some((dir, proto @ ("inet" | "inet6"))) if !dir.is_empty() => Ok(Self {
+                dir: dir.to_owned(),
+                proto: proto.to_owned(),
+            }),
+            _ => Err(format!("expected <dir>:inet or <dir>:inet6, got {}", value)),
+        }
+    }
+}
+
+impl TryFrom<String> for ExtraLeaseDir {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ExtraLeaseDir> for String {
+    fn from(extra: ExtraLeaseDir) -> Self {
+        format!("{}:{}", extra.dir, extra.proto)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Config file could not be read
+    Read { path: String, source: io::Error },
+    /// Config file is not valid
+    Parse {
+        path: String,
+        source: toml::de::Error,
+    },
+    /// Interface pattern is not a valid regex
+    Pattern {
+        pattern: String,
+        source: regex::Error,
+    },
+    /// Path could not be expanded
+    Path { path: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, source } => {
+                write!(f, "Failed to read config file {}: {}", path, source)
+            }
+            ConfigError::Parse { path, source } => {
+                write!(f, "Invalid config file {}: {}", path, source)
+            }
+            ConfigError::Pattern { pattern, source } => {
+                write!(f, "Invalid interface pattern {}: {}", pattern, source)
+            }
+            ConfigError::Path { path, reason } => {
+                write!(f, "Failed to expand path {}: {}", path, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            foreground: false,
+            pid_file: String::from(pidfile::default_path()),
+            root_dir: String::from("/"),
+            user: None,
+            group: None,
+            scripts_dir: String::from("/etc/dhcpleasemon"),
+            trigger_script_prefix: String::from("lease_trigger_"),
+            trigger_script_prefix_ipv6: String::from("lease_trigger_"),
+            init_script: None,
+            init_script_required: false,
+            cleanup_script: None,
+            require_scripts: false,
+            require_lease_dirs: false,
+            pre_hook: None,
+            pre_hook_required: false,
+            post_hook: None,
+            shared_script: None,
+            dhcp_lease_dir: String::from("/var/db/dhcpleased"),
+            dhcp6_lease_dir: String::from("/var/db/dhcp6leased"),
+            lease_format: LeaseFormat::default(),
+            dhcpcd_path: String::from(lease::default_dhcpcd_path()),
+            lease_file_template: String::from("{iface}"),
+            lease6_file_template: String::from("{iface}"),
+            interval: Duration::from_secs(1),
+            debounce: Duration::ZERO,
+            cooldown: Duration::ZERO,
+            expiry_warning: Duration::ZERO,
+            jitter: 0,
+            fixed_rate: false,
+            route_fallback_global: false,
+            route_lookup: RouteLookup::default(),
+            netstat_path: String::from(route::default_netstat_path()),
+            trigger_on_startup: true,
+            no_trigger_on_rediscovery: false,
+            trigger_on_renew: false,
+            script_timeout: 0,
+            stop_on_error: false,
+            unified_trigger: false,
+            retry_count: 0,
+            retry_delay: Duration::from_secs(1),
+            detect_by: DetectBy::default(),
+            state_file: None,
+            status_file: None,
+            control_socket: None,
+            history_size: 20,
+            interfaces: Vec::new(),
+            interface_regex: false,
+            auto_discover: false,
+            ignore: Vec::new(),
+            extra_lease_dir: Vec::new(),
+            ipv6: false,
+            dhclient_compat: false,
+            json_stdin: false,
+            args_mode: ArgsMode::default(),
+            env_prefix: String::from("DHCP_"),
+            list_separator: String::from(" "),
+            webhook_url: None,
+            metrics_listen: None,
+            mqtt_broker: None,
+            mqtt_topic_prefix: String::from("dhcpleasemon"),
+            mqtt_username: None,
+            mqtt_password: None,
+            shadow: false,
+            dry_run: false,
+            no_sandbox: false,
+            journal: false,
+            no_color: false,
+            verbosity: 0,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file given on the command line (if any) and applies
+    /// the command line arguments on top of it
+    pub fn from_args(args: &Args) -> Result<Self, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+        config.merge_args(args);
+        config.compile_patterns()?;
+        config.expand_paths()?;
+        Ok(config)
+    }
+
+    /// Expands `~` and environment variables in the paths
+    fn expand_paths(&mut self) -> Result<(), ConfigError> {
+        let mut paths: Vec<&mut String> = vec![
+            &mut self.pid_file,
+            &mut self.root_dir,
+            &mut self.scripts_dir,
+            &mut self.dhcp_lease_dir,
+            &mut self.dhcp6_lease_dir,
+            &mut self.netstat_path,
+            &mut self.dhcpcd_path,
+        ];
+        paths.extend(
+            [
+                &mut self.init_script,
+                &mut self.cleanup_script,
+                &mut self.pre_hook,
+                &mut self.post_hook,
+                &mut self.shared_script,
+                &mut self.state_file,
+                &mut self.status_file,
+                &mut self.control_socket,
+            ]
+            .into_iter()
+            .flatten(),
+        );
+        for iface in &mut self.interfaces {
+            paths.extend(iface.trigger_script.as_mut());
+            paths.extend(iface.trigger_script_ipv6.as_mut());
+            paths.extend(iface.dhcp_lease_dir.as_mut());
+            paths.extend(iface.dhcp6_lease_dir.as_mut());
+        }
+        paths.extend(self.extra_lease_dir.iter_mut().map(|extra| &mut extra.dir));
+
+        for path in paths {
+            *path = expand_path(path).map_err(|reason| ConfigError::Path {
+                path: path.to_owned(),
+                reason,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Loads a TOML config file, missing settings get the defaults
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_string(),
+            source,
+        })?;
+        toml::from_str(&content).map_err(|source| ConfigError::Parse {
+            path: path.to_string(),
+            source,
+        })
+    }
+
+    /// Overrides the settings given on the command line
+    fn merge_args(&mut self, args: &Args) {
+        fn merge<T: Clone>(value: &mut T, arg: &Option<T>) {
+            if let Some(arg) = arg {
+                *value = arg.clone();
+            }
+        }
+
+        fn merge_option<T: Clone>(value: &mut Option<T>, arg: &Option<T>) {
+            if arg.is_some() {
+                *value = arg.clone();
+            }
+        }
+
+        self.foreground |= args.foreground;
+        merge(&mut self.pid_file, &args.pid_file);
+        merge(&mut self.root_dir, &args.root_dir);
+        merge_option(&mut self.user, &args.user);
+        merge_option(&mut self.group, &args.group);
+        merge(&mut self.scripts_dir, &args.scripts_dir);
+        merge(&mut self.trigger_script_prefix, &args.trigger_script_prefix);
+        merge(
+            &mut self.trigger_script_prefix_ipv6,
+            &args.trigger_script_prefix_ipv6,
+        );
+        merge_option(&mut self.init_script, &args.init_script);
+        self.init_script_required |= args.init_script_required;
+        merge_option(&mut self.cleanup_script, &args.cleanup_script);
+        self.require_scripts |= args.require_scripts;
+        self.require_lease_dirs |= args.require_lease_dirs;
+        merge_option(&mut self.pre_hook, &args.pre_hook);
+        self.pre_hook_required |= args.pre_hook_required;
+        merge_option(&mut self.post_hook, &args.post_hook);
+        merge_option(&mut self.shared_script, &args.shared_script);
+        merge(&mut self.dhcp_lease_dir, &args.dhcp_lease_dir);
+        merge(&mut self.dhcp6_lease_dir, &args.dhcp6_lease_dir);
+        merge(&mut self.lease_format, &args.lease_format);
+        merge(&mut self.dhcpcd_path, &args.dhcpcd_path);
+        merge(&mut self.lease_file_template, &args.lease_file_template);
+        merge(&mut self.lease6_file_template, &args.lease6_file_template);
+        merge(&mut self.interval, &args.interval);
+        merge(&mut self.debounce, &args.debounce);
+        merge(&mut self.cooldown, &args.cooldown);
+        merge(&mut self.expiry_warning, &args.expiry_warning);
+        merge(&mut self.jitter, &args.jitter);
+        self.fixed_rate |= args.fixed_rate;
+        self.route_fallback_global |= args.route_fallback_global;
+        merge(&mut self.route_lookup, &args.route_lookup);
+        merge(&mut self.netstat_path, &args.netstat_path);
+        merge_option(&mut self.state_file, &args.state_file);
+        merge_option(&mut self.status_file, &args.status_file);
+        merge_option(&mut self.control_socket, &args.control_socket);
+        merge(&mut self.history_size, &args.history_size);
+        self.no_trigger_on_rediscovery |= args.no_trigger_on_rediscovery;
+        self.trigger_on_renew |= args.trigger_on_renew;
+        merge(&mut self.script_timeout, &args.script_timeout);
+        self.stop_on_error |= args.stop_on_error;
+        self.unified_trigger |= args.unified_trigger;
+        merge(&mut self.retry_count, &args.retry_count);
+        merge(&mut self.retry_delay, &args.retry_delay);
+        merge(&mut self.detect_by, &args.detect_by);
+        if args.trigger_on_startup {
+            self.trigger_on_startup = true;
+        } else if args.no_trigger_on_startup {
+            self.trigger_on_startup = false;
+        }
+        self.ipv6 |= args.ipv6;
+        self.dhclient_compat |= args.dhclient_compat;
+        self.json_stdin |= args.json_stdin;
+        merge(&mut self.args_mode, &args.args_mode);
+        merge(&mut self.env_prefix, &args.env_prefix);
+        merge(&mut self.list_separator, &args.list_separator);
+        merge_option(&mut self.webhook_url, &args.webhook_url);
+        merge_option(&mut self.metrics_listen, &args.metrics_listen);
+        merge_option(&mut self.mqtt_broker, &args.mqtt_broker);
+        merge(&mut self.mqtt_topic_prefix, &args.mqtt_topic_prefix);
+        merge_option(&mut self.mqtt_username, &args.mqtt_username);
+        merge_option(&mut self.mqtt_password, &args.mqtt_password);
+        self.shadow |= args.shadow;
+        self.dry_run |= args.dry_run;
+        self.no_sandbox |= args.no_sandbox;
+        self.journal |= args.journal;
+        self.no_color |= args.no_color;
+        if args.verbosity > 0 {
+            self.verbosity = args.verbosity;
+        }
+
+        self.interface_regex |= args.interface_regex;
+        self.auto_discover |= args.auto_discover;
+        if !args.ignore.is_empty() {
+            self.ignore = args.ignore.clone();
+        }
+        if !args.extra_lease_dir.is_empty() {
+            self.extra_lease_dir = args.extra_lease_dir.clone();
+        }
+
+        // Interfaces given on the command line replace the configured ones,
+        // keeping the per-interface settings of those that are configured
+        if !args.interfaces.is_empty() {
+            self.interfaces = args
+                .interfaces
+                .iter()
+                .map(|name| {
+                    self.interfaces
+                        .iter()
+                        .find(|i| i.name == *name)
+                        .cloned()
+                        .unwrap_or_else(|| InterfaceConfig::new(name))
+                })
+                .collect();
+        }
+    }
+
+    /// Log level for the verbosity: warn, info, debug, trace
+    pub fn log_level(&self) -> LevelFilter {
+        match self.verbosity {
+            0 => LevelFilter::Warn,
+            1 => LevelFilter::Info,
+            2 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    /// The configuration as a config file (`--print-config`): every setting,
+    /// with the interfaces' settings resolved, the MQTT password hidden
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        let mut config = self.clone();
+        if config.mqtt_password.is_some() {
+            config.mqtt_password = Some(String::from("********"));
+        }
+        for iface in &mut config.interfaces {
+            iface
+                .trigger_script_prefix
+                .get_or_insert_with(|| self.trigger_script_prefix.to_owned());
+            iface
+                .trigger_script_prefix_ipv6
+                .get_or_insert_with(|| self.trigger_script_prefix_ipv6.to_owned());
+            iface
+                .dhcp_lease_dir
+                .get_or_insert_with(|| self.dhcp_lease_dir.to_owned());
+            iface
+                .dhcp6_lease_dir
+                .get_or_insert_with(|| self.dhcp6_lease_dir.to_owned());
+        }
+        toml::to_string(&config)
+    }
+
+    /// Drops the interfaces without a name and the repeated ones (the first
+    /// entry is kept, with its settings), warning about each; warns about
+    /// the interfaces' variables that are the daemon's own (they are left
+    /// out)
+    pub fn check_interfaces(&mut self) {
+        let mut seen = HashSet::new();
+        self.interfaces.retain(|iface| {
+            if iface.name.trim().is_empty() {
+                warn!("Ignoring an interface with an empty name");
+                return false;
+            }
+            if seen.insert(iface.name.to_owned()) {
+                return true;
+            }
+            warn!(
+                "Interface {} is listed more than once, ignoring the repeat",
+                iface.name
+            );
+            false
+        });
+
+        for iface in &self.interfaces {
+            for name in iface.env.keys().filter(|name| self.is_reserved_env(name)) {
+                warn!(
+                    "Variable {} of interface {} collides with the daemon's variables, ignoring it",
+                    name, iface.name
+                );
+            }
+        }
+    }
+
+    /// Is the variable name one of the daemon's own (`--env-prefix`), which
+    /// the interfaces' variables cannot override?
+    pub fn is_reserved_env(&self, name: &str) -> bool {
+        name.starts_with(&trigger::env_name(&self.env_prefix, "DHCP_"))
+            || name.starts_with(&trigger::env_name(&self.env_prefix, "DHCP6_"))
+    }
+
+    /// Every directory lease files are read from (the global, per-interface
+    /// and extra ones; the IPv6 ones only with `ipv6`)
+    pub fn lease_dirs(&self) -> BTreeSet<&str> {
+        let mut lease_dirs = BTreeSet::from([self.dhcp_lease_dir.as_str()]);
+        if self.ipv6 {
+            lease_dirs.insert(&self.dhcp6_lease_dir);
+        }
+        for iface in &self.interfaces {
+            lease_dirs.extend(iface.dhcp_lease_dir.as_deref());
+            if self.ipv6 {
+                lease_dirs.extend(iface.dhcp6_lease_dir.as_deref());
+            }
+        }
+        lease_dirs.extend(
+            self.extra_lease_dir
+                .iter()
+                .filter(|extra| extra.proto == "inet" || self.ipv6)
+                .map(|extra| extra.dir.as_str()),
+        );
+        lease_dirs
+    }
+
+    /// Warns about the files the daemon writes that are in a lease
+    /// directory: lease directories are only read and may be mounted
+    /// read-only (e.g. in a jail or container)
+    pub fn check_written_paths(&self) {
+        let written_paths = [
+            (
+                "PID file",
+                (!self.foreground).then_some(self.pid_file.as_str()),
+            ),
+            ("State file", self.state_file.as_deref()),
+            ("Status file", self.status_file.as_deref()),
+            ("Control socket", self.control_socket.as_deref()),
+        ];
+        let lease_dirs = self.lease_dirs();
+        for (name, path) in written_paths {
+            let Some(path) = path else {
+                continue;
+            };
+            if let Some(lease_dir) = lease_dirs
+                .iter()
+                .find(|lease_dir| Path::new(path).starts_with(lease_dir))
+            {
+                warn!(
+                    "{} {} is in the lease directory {}, which may be read-only",
+                    name, path, lease_dir
+                );
+            }
+        }
+    }
+
+    /// Names of the monitored interfaces (not including the patterns and
+    /// the ignored interfaces)
+    pub fn interface_names(&self) -> Vec<String> {
+        self.interfaces
+            .iter()
+            .filter(|i| !self.is_pattern(i) && !self.is_ignored(&i.name))
+            .map(|i| i.name.to_owned())
+            .collect()
+    }
+
+    /// Is the interface listed (by name or glob) with --ignore?
+    pub fn is_ignored(&self, iface_name: &str) -> bool {
+        self.ignore
+            .iter()
+            .any(|ignore| ignore == iface_name || fnmatch(ignore, iface_name))
+    }
+
+    /// Settings of a monitored interface: its own entry, else the first
+    /// pattern that matches it
+    pub fn interface(&self, iface_name: &str) -> Option<&InterfaceConfig> {
+        self.interfaces
+            .iter()
+            .find(|i| i.name == iface_name)
+            .or_else(|| {
+                self.interfaces
+                    .iter()
+                    .find(|i| self.is_pattern(i) && self.pattern_matches(i, iface_name))
+            })
+    }
+
+    /// Are there interface patterns to match the lease files against?
+    pub fn has_interface_patterns(&self) -> bool {
+        self.interfaces.iter().any(|i| self.is_pattern(i))
+    }
+
+    /// Does any of the interface patterns match the interface name?
+    pub fn matches_interface_pattern(&self, iface_name: &str) -> bool {
+        self.interfaces
+            .iter()
+            .any(|i| self.is_pattern(i) && self.pattern_matches(i, iface_name))
+    }
+
+    /// With --interface-regex every entry is a pattern, otherwise the ones
+    /// with glob characters are
+    fn is_pattern(&self, iface: &InterfaceConfig) -> bool {
+        self.interface_regex || iface.name.contains(['*', '?', '['])
+    }
+
+    /// Matches the whole interface name against the entry's regex (see
+    /// `compile_patterns`) or shell glob
+    fn pattern_matches(&self, iface: &InterfaceConfig, iface_name: &str) -> bool {
+        if self.interface_regex {
+            return iface
+                .regex
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(iface_name));
+        }
+
+        fnmatch(&iface.name, iface_name)
+    }
+
+    /// Compiles the interface regexes (anchored, they match the whole
+    /// name), once for all the scans
+    fn compile_patterns(&mut self) -> Result<(), ConfigError> {
+        if !self.interface_regex {
+            return Ok(());
+        }
+        for iface in &mut self.interfaces {
+            let regex = Regex::new(&format!("^(?:{})$", iface.name)).map_err(|source| {
+                ConfigError::Pattern {
+                    pattern: iface.name.to_owned(),
+                    source,
+                }
+            })?;
+            iface.regex = Some(regex);
+        }
+        Ok(())
+    }
+}
+
+/// Matches the whole name against a shell glob
+fn fnmatch(pattern: &str, name: &str) -> bool {
+    match (CString::new(pattern), CString::new(name)) {
+        (Ok(c_pattern), Ok(c_name)) => unsafe {
+            libc::fnmatch(c_pattern.as_ptr(), c_name.as_ptr(), 0) == 0
+        },
+        _ => false,
+    }
+}
+
+/// Expands a leading `~` (or `~/`) to the home directory and `$VAR` or
+/// `${VAR}` to the value of the environment variable, other paths are left
+/// as they are
+pub fn expand_path(path: &str) -> Result<String, String> {
+    let var = |name: &str| {
+        env::var(name).map_err(|_| format!("environment variable {} is not set", name))
+    };
+
+    let mut expanded = String::new();
+    let mut rest = path;
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push_str(&var("HOME")?);
+        rest = &rest[1..];
+    }
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, next) = match after.strip_prefix('{') {
+            Some(braced) => match braced.split_once('}') {
+                Some((name, next)) => (name, next),
+                None => return Err(String::from("unterminated ${")),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if name.is_empty() {
+            // A lone `$` is kept
+            expanded.push('$');
+        } else {
+            expanded.push_str(&var(name)?);
+        }
+        rest = next;
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// Parses a duration like `500ms`, `2s` or `5m`, or a plain number of seconds
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    match value.parse::<u64>() {
+        Ok(secs) => Ok(Duration::from_secs(secs)),
+        Err(_) => humantime::parse_duration(value).map_err(|e| e.to_string()),
+    }
+}
+
+/// Parses the separator of the list values, `\n` and `\t` stand for a
+/// newline and a tab
+pub fn parse_separator(value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Err(String::from("separator must not be empty"));
+    }
+    Ok(value.replace("\\n", "\n").replace("\\t", "\t"))
+}
+
+/// Parses a scan interval: a duration (see `parse_duration`) greater than
+/// zero
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let interval = parse_duration(value)?;
+    if interval.is_zero() {
+        return Err(String::from("interval must be greater than zero"));
+    }
+    Ok(interval)
+}
+
+/// Reads the interval from the config file, as a string or a number of seconds
+fn deserialize_interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    parse_interval(&deserialize_duration_value(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// Reads a duration from the config file, as a string or a number of seconds
+fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    parse_duration(&deserialize_duration_value(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// Reads the list separator from the config file (see `parse_separator`)
+fn deserialize_separator<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    parse_separator(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// Reads a percentage (0-100) from the config file
+fn deserialize_percent<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let percent = u8::deserialize(deserializer)?;
+    if percent > 100 {
+        return Err(serde::de::Error::custom(format!(
+            "{} is not a percentage (0-100)",
+            percent
+        )));
+    }
+    Ok(percent)
+}
+
+/// Writes a duration the way it is read, e.g. `1m 30s`
+fn serialize_duration<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+}
+
+fn deserialize_duration_value<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Interval {
+        Secs(u64),
+        Duration(String),
+    }
+
+    Ok(match Interval::deserialize(deserializer)? {
+        Interval::Secs(secs) => secs.to_string(),
+        Interval::Duration(value) => value,
+    })
+}
+
+impl InterfaceConfig {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            trigger_script: None,
+            trigger_script_ipv6: None,
+            trigger_script_prefix: None,
+            trigger_script_prefix_ipv6: None,
+            dhcp_lease_dir: None,
+            dhcp6_lease_dir: None,
+            env: BTreeMap::new(),
+            regex: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_path_keeps_plain_paths() {
+        assert_eq!(
+            expand_path("/etc/dhcpleasemon").unwrap(),
+            "/etc/dhcpleasemon"
+        );
+        assert_eq!(expand_path("relative/~").unwrap(), "relative/~");
+        assert_eq!(expand_path("/price/$").unwrap(), "/price/$");
+    }
+
+    #[test]
+    fn expand_path_expands_home() {
+        let home = env::var("HOME").unwrap();
+        assert_eq!(expand_path("~").unwrap(), home);
+        assert_eq!(
+            expand_path("~/scripts").unwrap(),
+            format!("{}/scripts", home)
+        );
+        assert_eq!(expand_path("~user/scripts").unwrap(), "~user/scripts");
+    }
+
+    #[test]
+    fn expand_path_expands_variables() {
+        env::set_var("DHCPLEASEMON_TEST_DIR", "/srv/dhcp");
+        assert_eq!(
+            expand_path("$DHCPLEASEMON_TEST_DIR/leases").unwrap(),
+            "/srv/dhcp/leases"
+        );
+        assert_eq!(
+            expand_path("${DHCPLEASEMON_TEST_DIR}_v6").unwrap(),
+            "/srv/dhcp_v6"
+        );
+    }
+
+    #[test]
+    fn expand_path_fails_on_unset_variable() {
+        assert!(expand_path("$DHCPLEASEMON_TEST_UNSET/leases").is_err());
+        assert!(expand_path("${DHCPLEASEMON_TEST_DIR").is_err());
+    }
+
+    fn patterns(interface_regex: bool, names: &[&str]) -> Config {
+        let mut config = Config {
+            interfaces: names
+                .iter()
+                .map(|name| InterfaceConfig::new(name))
+                .collect(),
+            interface_regex,
+            ..Default::default()
+        };
+        config.compile_patterns().unwrap();
+        config
+    }
+
+    #[test]
+    fn interface_regex_matches_whole_name() {
+        let config = patterns(true, &["em[0-9]+", "vlan.*"]);
+        assert!(config.interfaces.iter().all(|i| i.regex.is_some()));
+        assert!(config.matches_interface_pattern("em0"));
+        assert!(config.matches_interface_pattern("em12"));
+        assert!(config.matches_interface_pattern("vlan100"));
+        assert!(!config.matches_interface_pattern("em0a"));
+        assert!(!config.matches_interface_pattern("xem0"));
+        assert_eq!(config.interface("vlan100").unwrap().name, "vlan.*");
+        assert!(config.interface_names().is_empty());
+    }
+
+    #[test]
+    fn invalid_interface_regex_is_an_error() {
+        let mut config = Config {
+            interfaces: vec![InterfaceConfig::new("em(")],
+            interface_regex: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.compile_patterns(),
+            Err(ConfigError::Pattern { .. })
+        ));
+    }
+
+    #[test]
+    fn interface_globs() {
+        let config = patterns(false, &["em0", "vlan*"]);
+        assert!(config.interfaces.iter().all(|i| i.regex.is_none()));
+        assert!(config.matches_interface_pattern("vlan100"));
+        assert!(!config.matches_interface_pattern("em1"));
+        assert_eq!(config.interface("em0").unwrap().name, "em0");
+        assert_eq!(config.interface_names(), vec!["em0"]);
+    }
+
+    #[test]
+    fn list_separator_escapes() {
+        assert_eq!(parse_separator(",").unwrap(), ",");
+        assert_eq!(parse_separator("\\n").unwrap(), "\n");
+        assert_eq!(parse_separator(";\\t").unwrap(), ";\t");
+        assert!(parse_separator("").is_err());
+
+        let config: Config = toml::from_str("list_separator = '\\n'").unwrap();
+        assert_eq!(config.list_separator, "\n");
+        assert!(toml::from_str::<Config>("list_separator = ''").is_err());
+        assert_eq!(Config::default().list_separator, " ");
+    }
+
+    #[test]
+    fn interface_env_from_config_file() {
+        let config: Config = toml::from_str(
+            "[[interface]]\nname = \"em0\"\nenv = { ZONE = \"dmz\", TABLE = \"100\" }\n\
+             [[interface]]\nname = \"em1\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.interface("em0").unwrap().env,
+            BTreeMap::from([
+                (String::from("TABLE"), String::from("100")),
+                (String::from("ZONE"), String::from("dmz")),
+            ])
+        );
+        assert!(config.interface("em1").unwrap().env.is_empty());
+        assert!(!config.to_toml().unwrap().contains("[interface.env]\n\n"));
+    }
+
+    #[test]
+    fn reserved_env_follows_the_prefix() {
+        let mut config = Config::default();
+        assert!(config.is_reserved_env("DHCP_IP_ADDR"));
+        assert!(config.is_reserved_env("DHCP6_ZONE"));
+        assert!(!config.is_reserved_env("ZONE"));
+        assert!(!config.is_reserved_env("DHCPZONE"));
+        config.env_prefix = String::from("LEASE_");
+        assert!(config.is_reserved_env("LEASE_ZONE"));
+        assert!(config.is_reserved_env("LEASE6_ZONE"));
+        assert!(!config.is_reserved_env("DHCP_ZONE"));
+    }
+
+    #[test]
+    fn repeated_interfaces_keep_the_first_entry() {
+        let mut config = Config {
+            interfaces: vec![
+                InterfaceConfig {
+                    trigger_script: Some("first".to_string()),
+                    ..InterfaceConfig::new("em0")
+                },
+                InterfaceConfig::new("em1"),
+                InterfaceConfig::new(" "),
+                InterfaceConfig {
+                    trigger_script: Some("second".to_string()),
+                    dhcp_lease_dir: Some("/tmp/leases".to_string()),
+                    ..InterfaceConfig::new("em0")
+                },
+            ],
+            ..Default::default()
+        };
+        config.check_interfaces();
+        assert_eq!(config.interface_names(), vec!["em0", "em1"]);
+        let em0 = config.interface("em0").unwrap();
+        assert_eq!(em0.trigger_script.as_deref(), Some("first"));
+        assert_eq!(em0.dhcp_lease_dir, None);
+    }
+}
+
+// EOF
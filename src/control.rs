@@ -0,0 +1,227 @@
+//! Control socket (`--control-socket`): a Unix domain socket on which the
+//! daemon answers status and history requests, and the `status` and
+//! `history` subcommands querying it
+//!
+//! A request is a single line, `status [<iface>]` or `history [<iface>]`; the
+//! reply is the status (as in the status file) or the recent lease changes
+//! as JSON, or a line starting with `error:`.
+
+use crate::history::SharedHistory;
+use log::{debug, error};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// How long a client gets to send its request
+const CONTROL_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The status the control socket answers with, updated by the monitor
+pub type SharedStatus = Arc<Mutex<serde_json::Value>>;
+
+/// Starts answering requests on the socket, replacing a stale one
+pub fn serve(path: &str, status: SharedStatus, history: SharedHistory) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+
+    thread::Builder::new()
+        .name(String::from("control"))
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = handle(stream, &status, &history) {
+                            debug!("Control request failed: {}", e);
+                        }
+                    }
+                    Err(e) => error!("Failed to accept control connection: {}", e),
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Answers a single request
+fn handle(stream: UnixStream, status: &SharedStatus, history: &SharedHistory) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONTROL_REQUEST_TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+
+    let mut words = request.split_whitespace();
+    let reply = match (words.next(), words.next()) {
+        (Some("status"), iface_name) => {
+            let status = status.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            match iface_name {
+                None => status.to_string(),
+                Some(iface_name) => match status["interfaces"].get(iface_name) {
+                    Some(iface_status) => serde_json::json!({
+                        "updated": status["updated"],
+                        "interfaces": { iface_name: iface_status },
+                    })
+                    .to_string(),
+                    None => format!("error: unknown interface {}", iface_name),
+                },
+            }
+        }
+        (Some("history"), iface_name) => history
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .to_json(iface_name)
+            .to_string(),
+        _ => format!("error: invalid request: {}", request.trim()),
+    };
+
+    (&stream).write_all(reply.as_bytes())?;
+    (&stream).write_all(b"\n")
+}
+
+/// The `status` subcommand: queries the daemon and prints the leases,
+/// returns the exit status
+pub fn print_status(path: &str, iface_name: Option<&str>) -> i32 {
+    let status = match query_json(path, "status", iface_name) {
+        Ok(status) => status,
+        Err(exit_status) => return exit_status,
+    };
+
+    let interfaces = status["interfaces"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+    if interfaces.is_empty() {
+        println!("No leases");
+    }
+    for (iface_name, iface_status) in &interfaces {
+        let lease = &iface_status["lease"];
+        if lease.is_object() {
+            println!(
+                "{} inet: {} route {}",
+                iface_name,
+                lease["ip_addr"].as_str().unwrap_or(""),
+                none_if_empty(&lease["route_addr"]),
+            );
+        }
+        let lease6 = &iface_status["lease6"];
+        if lease6.is_object() {
+            let mut addrs: Vec<String> = lease6["ip6_prefixes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|prefix| {
+                    format!(
+                        "{}/{}",
+                        prefix[0].as_str().unwrap_or(""),
+                        prefix[1].as_str().unwrap_or("")
+                    )
+                })
+                .collect();
+            if let Some(ip6_addr) = lease6["ip6_addr"].as_str().filter(|a| !a.is_empty()) {
+                addrs.push(ip6_addr.to_owned());
+            }
+            println!(
+                "{} inet6: {} route {}",
+                iface_name,
+                addrs.join(" "),
+                none_if_empty(&lease6["route6_addr"]),
+            );
+        }
+        if iface_status["failed"].as_bool() == Some(true) {
+            println!("{} trigger failed", iface_name);
+        }
+        if let Some(last_change) = iface_status["last_change"].as_u64() {
+            println!(
+                "{} last change: {}",
+                iface_name,
+                humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(last_change))
+            );
+        }
+    }
+
+    0
+}
+
+/// The `history` subcommand: queries the daemon and prints the recent lease
+/// changes, returns the exit status
+pub fn print_history(path: &str, iface_name: Option<&str>) -> i32 {
+    let history = match query_json(path, "history", iface_name) {
+        Ok(history) => history,
+        Err(exit_status) => return exit_status,
+    };
+
+    let interfaces = history["interfaces"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+    if interfaces.is_empty() {
+        println!("No lease changes");
+    }
+    for (iface_name, entries) in &interfaces {
+        for entry in entries.as_array().into_iter().flatten() {
+            let timestamp = Duration::from_secs(entry["timestamp"].as_u64().unwrap_or(0));
+            println!(
+                "{} {} {} {}: {} -> {} (trigger: {})",
+                humantime::format_rfc3339_seconds(UNIX_EPOCH + timestamp),
+                iface_name,
+                entry["family"].as_str().unwrap_or(""),
+                entry["event"].as_str().unwrap_or(""),
+                none_if_empty(&entry["old_addr"]),
+                none_if_empty(&entry["new_addr"]),
+                entry["trigger"].as_str().unwrap_or(""),
+            );
+        }
+    }
+
+    0
+}
+
+fn none_if_empty(value: &serde_json::Value) -> &str {
+    match value.as_str() {
+        Some("") | None => "none",
+        Some(value) => value,
+    }
+}
+
+/// Sends a request and parses the JSON reply; errors are printed, the exit
+/// status is returned for them
+fn query_json(
+    path: &str,
+    request: &str,
+    iface_name: Option<&str>,
+) -> Result<serde_json::Value, i32> {
+    let reply = match query(path, request, iface_name) {
+        Ok(reply) => reply,
+        Err(e) => {
+            eprintln!("Error: Failed to query the daemon on {}: {}", path, e);
+            return Err(1);
+        }
+    };
+
+    if let Some(e) = reply.strip_prefix("error: ") {
+        eprintln!("Error: {}", e.trim());
+        return Err(1);
+    }
+    serde_json::from_str(&reply).map_err(|e| {
+        eprintln!("Error: Invalid reply from the daemon: {}", e);
+        1
+    })
+}
+
+/// Sends a request (`status`, `history`), returns the reply
+fn query(path: &str, request: &str, iface_name: Option<&str>) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    match iface_name {
+        Some(iface_name) => writeln!(stream, "{} {}", request, iface_name)?,
+        None => writeln!(stream, "{}", request)?,
+    }
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+// EOF
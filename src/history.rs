@@ -0,0 +1,100 @@
+//! Recent lease changes per interface (`--history-size`), kept in memory and
+//! answered on the control socket
+
+use crate::unix_time;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// The history the control socket answers with, updated by the monitor and
+/// the trigger workers
+pub type SharedHistory = Arc<Mutex<History>>;
+
+/// Outcome of the trigger scripts of a change
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerStatus {
+    /// No script was run (none found, shadow mode or dry run)
+    NotRun,
+    /// Queued or still running
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+/// A lease change
+#[derive(Serialize, Debug, Clone)]
+pub struct HistoryEntry {
+    /// Sequence number, to update the trigger status
+    #[serde(skip)]
+    id: u64,
+    /// When the change was triggered (seconds since the epoch)
+    pub timestamp: u64,
+    pub family: &'static str,
+    pub event: String,
+    pub old_addr: String,
+    pub new_addr: String,
+    pub trigger: TriggerStatus,
+}
+
+#[derive(Default)]
+pub struct History {
+    interfaces: BTreeMap<String, VecDeque<HistoryEntry>>,
+    next_id: u64,
+}
+
+impl History {
+    /// Records a change, dropping the interface's oldest ones beyond size;
+    /// returns the entry's id
+    pub fn record(&mut self, iface_name: &str, mut entry: HistoryEntry, size: usize) -> u64 {
+        self.next_id += 1;
+        entry.id = self.next_id;
+
+        let entries = self.interfaces.entry(iface_name.to_owned()).or_default();
+        entries.push_back(entry);
+        while entries.len() > size {
+            entries.pop_front();
+        }
+        if entries.is_empty() {
+            self.interfaces.remove(iface_name);
+        }
+        self.next_id
+    }
+
+    /// Sets the trigger status of a recorded change (if it is still kept)
+    pub fn set_trigger(&mut self, iface_name: &str, id: u64, trigger: TriggerStatus) {
+        if let Some(entry) = self
+            .interfaces
+            .get_mut(iface_name)
+            .and_then(|entries| entries.iter_mut().find(|entry| entry.id == id))
+        {
+            entry.trigger = trigger;
+        }
+    }
+
+    /// The changes of every interface (or of the given one), oldest first
+    pub fn to_json(&self, iface_name: Option<&str>) -> serde_json::Value {
+        let interfaces: BTreeMap<&String, &VecDeque<HistoryEntry>> = self
+            .interfaces
+            .iter()
+            .filter(|(name, _)| iface_name.is_none_or(|iface_name| iface_name == name.as_str()))
+            .collect();
+        serde_json::json!({ "interfaces": interfaces })
+    }
+}
+
+impl HistoryEntry {
+    pub fn new(family: &'static str, event: String, old_addr: String, new_addr: String) -> Self {
+        Self {
+            id: 0,
+            timestamp: unix_time(),
+            family,
+            event,
+            old_addr,
+            new_addr,
+            trigger: TriggerStatus::NotRun,
+        }
+    }
+}
+
+// EOF
@@ -0,0 +1,1493 @@
+//! Lease files of the DHCP clients and the params read from them
+
+use crate::monitor::{interface_index, MonitorError};
+use crate::unix_time;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::process::Command;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LeaseParams {
+    pub iface_name: String,
+    #[serde(with = "empty_if_none")]
+    pub ip_addr: Option<Ipv4Addr>,
+    pub route_addr: String,
+    pub route_match: String,
+    pub next_server: String,
+    pub filename: String,
+    pub host_name: String,
+    pub domain_name: String,
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// Lease time offered (seconds)
+    #[serde(with = "empty_if_none")]
+    pub lease_time: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Lease6Params {
+    pub iface_name: String,
+    /// First delegated prefix
+    #[serde(with = "empty_if_none")]
+    pub ip6_prefix: Option<Ipv6Addr>,
+    #[serde(with = "empty_if_none")]
+    pub ip6_prefix_len: Option<u8>,
+    pub route6_addr: String,
+    pub route6_match: String,
+    /// All delegated prefixes (prefix, length)
+    #[serde(with = "prefixes")]
+    pub ip6_prefixes: Vec<(Ipv6Addr, u8)>,
+    /// Assigned address (ia_na)
+    #[serde(with = "empty_if_none")]
+    pub ip6_addr: Option<Ipv6Addr>,
+    pub dns_servers: Vec<Ipv6Addr>,
+}
+
+/// (De)serializes an optional value as a string, empty for none, as the
+/// lease fields were strings in the state file, the status and the JSON
+/// given to the scripts and the webhook
+mod empty_if_none {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_str(""),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        value.parse().map(Some).map_err(de::Error::custom)
+    }
+}
+
+/// (De)serializes the delegated prefixes as `[prefix, length]` string pairs,
+/// see above
+mod prefixes {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+    use std::net::Ipv6Addr;
+
+    pub fn serialize<S: Serializer>(
+        prefixes: &[(Ipv6Addr, u8)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        prefixes
+            .iter()
+            .map(|(ip_prefix, ip_prefix_len)| (ip_prefix.to_string(), ip_prefix_len.to_string()))
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(Ipv6Addr, u8)>, D::Error> {
+        Vec::<(String, String)>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(ip_prefix, ip_prefix_len)| {
+                Ok((
+                    ip_prefix.parse().map_err(de::Error::custom)?,
+                    ip_prefix_len.parse().map_err(de::Error::custom)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// The value, or empty for none (the environment of the scripts)
+pub fn or_empty<T: fmt::Display>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// The addresses, joined by the separator (the environment of the scripts)
+pub fn join_addrs<T: fmt::Display>(addrs: &[T], separator: &str) -> String {
+    addrs
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+impl LeaseParams {
+    /// Params of a lost lease (the lease file was removed)
+    pub fn down(iface_name: &str) -> Self {
+        Self {
+            iface_name: iface_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.ip_addr.is_none()
+    }
+}
+
+impl Lease6Params {
+    /// Params of a lost lease (the lease file was removed)
+    pub fn down(iface_name: &str) -> Self {
+        Self {
+            iface_name: iface_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.ip6_prefix.is_none() && self.ip6_addr.is_none()
+    }
+
+    /// Every delegated prefix as `prefix/length`
+    pub fn ip6_prefix_cidrs(&self) -> Vec<String> {
+        self.ip6_prefixes
+            .iter()
+            .map(|(ip_prefix, ip_prefix_len)| format!("{}/{}", ip_prefix, ip_prefix_len))
+            .collect()
+    }
+
+    /// The first delegated prefix as `prefix/length`, empty without one
+    pub fn ip6_prefix_cidr(&self) -> String {
+        match (self.ip6_prefix, self.ip6_prefix_len) {
+            (Some(ip_prefix), Some(ip_prefix_len)) => format!("{}/{}", ip_prefix, ip_prefix_len),
+            _ => String::new(),
+        }
+    }
+}
+
+/// What happened to a lease, passed to the trigger script as DHCP_EVENT
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum LeaseEvent {
+    /// First lease seen for the interface
+    Bound,
+    /// Lease file rewritten with the same params
+    Renew,
+    /// Lease params differ from the previous ones
+    Changed,
+    /// Lease lost (lease file removed)
+    Down,
+    /// Lease about to expire without having been renewed (`--expiry-warning`)
+    Expiring,
+}
+
+impl fmt::Display for LeaseEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let event = match self {
+            LeaseEvent::Bound => "bound",
+            LeaseEvent::Renew => "renew",
+            LeaseEvent::Changed => "changed",
+            LeaseEvent::Down => "down",
+            LeaseEvent::Expiring => "expiring",
+        };
+        write!(f, "{}", event)
+    }
+}
+
+/// Separator between the name of a lease file field and its value
+#[derive(Clone, Copy)]
+enum FieldDelimiter {
+    /// `name: value`
+    Colon,
+    /// `name value...`
+    Whitespace,
+    /// `NAME=value`
+    Equals,
+}
+
+impl FieldDelimiter {
+    /// Splits a lease file line into the field name and its value
+    fn split(self, line: &str) -> Option<(&str, &str)> {
+        match self {
+            FieldDelimiter::Colon => line.split_once(':'),
+            FieldDelimiter::Whitespace => line.trim().split_once(char::is_whitespace),
+            FieldDelimiter::Equals => line.split_once('='),
+        }
+    }
+
+    /// Every field of the content (name, value), trimmed, skipping blank
+    /// and comment (`#`) lines, e.g. in hand-edited files
+    fn fields(self, content: &str) -> impl Iterator<Item = (&str, &str)> {
+        content.lines().filter_map(move |line| {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let (ident, value) = self.split(line)?;
+            Some((ident.trim(), value.trim()))
+        })
+    }
+}
+
+/// Describes how a field is stored in a lease file
+struct LeaseField {
+    name: &'static str,
+    delimiter: FieldDelimiter,
+}
+
+/// IPv4 address (dhcpleased)
+const LEASE_FIELD_IP: LeaseField = LeaseField {
+    name: "ip",
+    delimiter: FieldDelimiter::Colon,
+};
+
+/// Boot server (dhcpleased)
+const LEASE_FIELD_NEXT_SERVER: LeaseField = LeaseField {
+    name: "next-server",
+    delimiter: FieldDelimiter::Colon,
+};
+
+/// Boot file name (dhcpleased)
+const LEASE_FIELD_FILENAME: LeaseField = LeaseField {
+    name: "filename",
+    delimiter: FieldDelimiter::Colon,
+};
+
+/// Host name offered by the server (dhcpleased)
+const LEASE_FIELD_HOST_NAME: LeaseField = LeaseField {
+    name: "host-name",
+    delimiter: FieldDelimiter::Colon,
+};
+
+/// Domain name (dhcpleased)
+const LEASE_FIELD_DOMAIN_NAME: LeaseField = LeaseField {
+    name: "domain-name",
+    delimiter: FieldDelimiter::Colon,
+};
+
+/// Name server, one line each (dhcpleased)
+const LEASE_FIELD_NAMESERVER: LeaseField = LeaseField {
+    name: "nameserver",
+    delimiter: FieldDelimiter::Colon,
+};
+
+/// Delegated IPv6 prefix (dhcp6leased)
+const LEASE6_FIELD_IA_PD: LeaseField = LeaseField {
+    name: "ia_pd",
+    delimiter: FieldDelimiter::Whitespace,
+};
+
+/// Assigned IPv6 address (dhcp6leased)
+const LEASE6_FIELD_IA_NA: LeaseField = LeaseField {
+    name: "ia_na",
+    delimiter: FieldDelimiter::Whitespace,
+};
+
+/// IPv6 name server, one line each (dhcp6leased)
+const LEASE6_FIELD_NAMESERVER: LeaseField = LeaseField {
+    name: "nameserver",
+    delimiter: FieldDelimiter::Whitespace,
+};
+
+/// IPv4 address (systemd-networkd)
+const NETWORKD_FIELD_ADDRESS: LeaseField = LeaseField {
+    name: "ADDRESS",
+    delimiter: FieldDelimiter::Equals,
+};
+
+/// Routers offered, space-separated (systemd-networkd)
+const NETWORKD_FIELD_ROUTER: LeaseField = LeaseField {
+    name: "ROUTER",
+    delimiter: FieldDelimiter::Equals,
+};
+
+/// Boot server (systemd-networkd)
+const NETWORKD_FIELD_NEXT_SERVER: LeaseField = LeaseField {
+    name: "NEXT_SERVER",
+    delimiter: FieldDelimiter::Equals,
+};
+
+/// Host name offered by the server (systemd-networkd)
+const NETWORKD_FIELD_HOST_NAME: LeaseField = LeaseField {
+    name: "HOSTNAME",
+    delimiter: FieldDelimiter::Equals,
+};
+
+/// Domain name (systemd-networkd)
+const NETWORKD_FIELD_DOMAIN_NAME: LeaseField = LeaseField {
+    name: "DOMAINNAME",
+    delimiter: FieldDelimiter::Equals,
+};
+
+/// Name servers offered, space-separated (systemd-networkd)
+const NETWORKD_FIELD_DNS: LeaseField = LeaseField {
+    name: "DNS",
+    delimiter: FieldDelimiter::Equals,
+};
+
+/// Lease time in seconds (systemd-networkd)
+const NETWORKD_FIELD_LIFETIME: LeaseField = LeaseField {
+    name: "LIFETIME",
+    delimiter: FieldDelimiter::Equals,
+};
+
+impl LeaseField {
+    /// Finds the value of the field in the lease file content
+    fn find(&self, content: &str) -> Option<String> {
+        self.find_all(content).next()
+    }
+
+    /// Finds the values of all occurrences of the field
+    fn find_all<'a>(&'a self, content: &'a str) -> impl Iterator<Item = String> + 'a {
+        self.delimiter
+            .fields(content)
+            .filter(|(ident, _)| *ident == self.name)
+            .map(|(_, value)| value.to_string())
+    }
+}
+
+/// Fields of an IPv4 lease, missing ones are empty
+#[derive(Debug, Default)]
+pub struct Lease {
+    pub ip_addr: Option<Ipv4Addr>,
+    /// First router offered
+    pub router: Option<Ipv4Addr>,
+    pub next_server: String,
+    pub filename: String,
+    pub host_name: String,
+    pub domain_name: String,
+    /// Name servers offered, in order
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// Lease time offered (seconds), if recorded
+    pub lease_time: Option<u32>,
+    /// When the lease expires (seconds since the epoch), if recorded
+    pub expiry: Option<u64>,
+}
+
+/// Fields of an IPv6 lease, missing ones are empty
+#[derive(Debug, Default)]
+pub struct Lease6 {
+    /// Delegated prefixes (prefix, length)
+    pub ip6_prefixes: Vec<(Ipv6Addr, u8)>,
+    /// Assigned address (ia_na)
+    pub ip6_addr: Option<Ipv6Addr>,
+    /// Name servers offered, in order
+    pub dns_servers: Vec<Ipv6Addr>,
+}
+
+/// The lease file holds an invalid value, it is not triggered
+fn invalid(lease_file_path: &str, reason: String) -> MonitorError {
+    MonitorError::InvalidLease {
+        path: lease_file_path.to_owned(),
+        reason,
+    }
+}
+
+/// Parses an address of the lease file, None if empty
+fn parse_addr<T: FromStr>(lease_file_path: &str, addr: &str) -> Result<Option<T>, MonitorError> {
+    if addr.is_empty() {
+        return Ok(None);
+    }
+    addr.parse()
+        .map(Some)
+        .map_err(|_| invalid(lease_file_path, format!("invalid address: {}", addr)))
+}
+
+/// Parses a list of addresses of the lease file
+fn parse_addrs<T: FromStr>(
+    lease_file_path: &str,
+    addrs: &[String],
+) -> Result<Vec<T>, MonitorError> {
+    addrs
+        .iter()
+        .map(|addr| {
+            addr.parse()
+                .map_err(|_| invalid(lease_file_path, format!("invalid address: {}", addr)))
+        })
+        .collect()
+}
+
+/// Parses a delegated prefix of the lease file and its length
+fn parse_prefix(
+    lease_file_path: &str,
+    ip_prefix: &str,
+    ip_prefix_len: &str,
+) -> Result<(Ipv6Addr, u8), MonitorError> {
+    let ip_prefix = ip_prefix
+        .parse()
+        .map_err(|_| invalid(lease_file_path, format!("invalid address: {}", ip_prefix)))?;
+    match ip_prefix_len.parse::<u8>() {
+        Ok(len) if len <= 128 => Ok((ip_prefix, len)),
+        _ => Err(invalid(
+            lease_file_path,
+            format!("invalid prefix length: {}", ip_prefix_len),
+        )),
+    }
+}
+
+/// Parses a lease time in seconds, None if empty
+fn parse_lease_time(lease_file_path: &str, lease_time: &str) -> Result<Option<u32>, MonitorError> {
+    if lease_time.is_empty() {
+        return Ok(None);
+    }
+    lease_time.parse().map(Some).map_err(|_| {
+        invalid(
+            lease_file_path,
+            format!("invalid lease time: {}", lease_time),
+        )
+    })
+}
+
+/// Parses the delegated prefixes of the lease file
+fn parse_prefixes(
+    lease_file_path: &str,
+    prefixes: &[(String, String)],
+) -> Result<Vec<(Ipv6Addr, u8)>, MonitorError> {
+    prefixes
+        .iter()
+        .map(|(ip_prefix, ip_prefix_len)| parse_prefix(lease_file_path, ip_prefix, ip_prefix_len))
+        .collect()
+}
+
+/// Format of the lease files, i.e. the DHCP client writing them
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseFormat {
+    /// OpenBSD dhcpleased and dhcp6leased
+    #[default]
+    Openbsd,
+    /// ISC dhclient
+    Isc,
+    /// systemd-networkd (IPv4 only)
+    #[serde(rename = "systemd-networkd")]
+    SystemdNetworkd,
+    /// dhcpcd (the leases are read with `dhcpcd -U`)
+    Dhcpcd,
+}
+
+/// Parser of the lease files of a DHCP client
+pub trait LeaseSource {
+    /// Parses the interface's lease file, None if it holds no lease (e.g.
+    /// only expired ones); fails with `IncompleteLeaseFile` while it lacks
+    /// the address (being written), and with `InvalidLease` if an address
+    /// is not valid
+    fn lease(
+        &self,
+        iface_name: &str,
+        lease_file_path: &str,
+        content: &str,
+    ) -> Result<Option<Lease>, MonitorError>;
+
+    /// Parses the interface's lease (IPv6) file, as above; incomplete while
+    /// it lacks both a prefix and an address
+    fn lease6(
+        &self,
+        iface_name: &str,
+        lease_file_path: &str,
+        content: &str,
+    ) -> Result<Option<Lease6>, MonitorError>;
+}
+
+/// The lease file lacks required fields, it is being written
+fn incomplete(lease_file_path: &str) -> MonitorError {
+    MonitorError::IncompleteLeaseFile {
+        path: lease_file_path.to_owned(),
+    }
+}
+
+/// Location of the dhcpcd binary (`--dhcpcd-path`); a bare name is looked
+/// up in PATH
+pub fn default_dhcpcd_path() -> &'static str {
+    if cfg!(any(target_os = "openbsd", target_os = "freebsd")) {
+        "/usr/local/sbin/dhcpcd"
+    } else {
+        "dhcpcd"
+    }
+}
+
+/// Creates the lease source for the given format (dhcpcd is run as
+/// `dhcpcd_path`)
+pub fn source(lease_format: LeaseFormat, dhcpcd_path: &str) -> Box<dyn LeaseSource> {
+    match lease_format {
+        LeaseFormat::Openbsd => Box::new(OpenbsdLeaseSource),
+        LeaseFormat::Isc => Box::new(IscLeaseSource),
+        LeaseFormat::SystemdNetworkd => Box::new(NetworkdLeaseSource),
+        LeaseFormat::Dhcpcd => Box::new(DhcpcdLeaseSource::new(dhcpcd_path)),
+    }
+}
+
+/// Parses the lease files of dhcpleased and dhcp6leased
+pub struct OpenbsdLeaseSource;
+
+impl LeaseSource for OpenbsdLeaseSource {
+    fn lease(
+        &self,
+        _iface_name: &str,
+        lease_file_path: &str,
+        content: &str,
+    ) -> Result<Option<Lease>, MonitorError> {
+        if LEASE_FIELD_IP.find(content).is_none() {
+            return Err(incomplete(lease_file_path));
+        }
+        let field = |field: &LeaseField| field.find(content).unwrap_or_default();
+        let lease = Lease {
+            ip_addr: parse_addr(lease_file_path, &field(&LEASE_FIELD_IP))?,
+            // Not recorded by dhcpleased
+            router: None,
+            next_server: field(&LEASE_FIELD_NEXT_SERVER),
+            filename: field(&LEASE_FIELD_FILENAME),
+            host_name: field(&LEASE_FIELD_HOST_NAME),
+            domain_name: field(&LEASE_FIELD_DOMAIN_NAME),
+            dns_servers: parse_addrs(
+                lease_file_path,
+                &LEASE_FIELD_NAMESERVER.find_all(content).collect::<Vec<_>>(),
+            )?,
+            // Not recorded by dhcpleased
+            lease_time: None,
+            expiry: None,
+        };
+        Ok(Some(lease))
+    }
+
+    fn lease6(
+        &self,
+        _iface_name: &str,
+        lease_file_path: &str,
+        content: &str,
+    ) -> Result<Option<Lease6>, MonitorError> {
+        // Malformed (short) entries do not count
+        let ip6_prefixes = get_lease_ip6_extract(content);
+        let ip6_addr = get_lease_ip6_addr(content);
+        if ip6_prefixes.is_empty() && ip6_addr.is_none() {
+            return Err(incomplete(lease_file_path));
+        }
+        Ok(Some(Lease6 {
+            ip6_prefixes: parse_prefixes(lease_file_path, &ip6_prefixes)?,
+            ip6_addr: parse_addr(lease_file_path, &ip6_addr.unwrap_or_default())?,
+            dns_servers: parse_addrs(
+                lease_file_path,
+                &LEASE6_FIELD_NAMESERVER
+                    .find_all(content)
+                    .collect::<Vec<_>>(),
+            )?,
+        }))
+    }
+}
+
+/// A `{ ... }` block of an ISC dhclient lease file: its statements and its
+/// nested blocks (with their headers), whitespace collapsed
+#[derive(Debug, Default)]
+struct IscBlock {
+    statements: Vec<String>,
+    blocks: Vec<(String, IscBlock)>,
+}
+
+impl IscBlock {
+    /// Parses the lease file content, None if a block is not closed (the
+    /// file is being written)
+    fn parse(content: &str) -> Option<Self> {
+        let normalize = |token: &str| token.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut stack = vec![(String::new(), IscBlock::default())];
+        let mut token = String::new();
+        let mut quoted = false;
+        let mut comment = false;
+
+        for c in content.chars() {
+            match c {
+                '\n' if comment => comment = false,
+                _ if comment => {}
+                '"' => {
+                    quoted = !quoted;
+                    token.push(c);
+                }
+                _ if quoted => token.push(c),
+                '#' => comment = true,
+                ';' => {
+                    if !token.trim().is_empty() {
+                        stack.last_mut()?.1.statements.push(normalize(&token));
+                    }
+                    token.clear();
+                }
+                '{' => {
+                    stack.push((normalize(&token), IscBlock::default()));
+                    token.clear();
+                }
+                '}' => {
+                    let block = stack.pop()?;
+                    stack.last_mut()?.1.blocks.push(block);
+                    token.clear();
+                }
+                _ => token.push(c),
+            }
+        }
+
+        match stack.pop() {
+            Some((_, root)) if stack.is_empty() => Some(root),
+            _ => None,
+        }
+    }
+
+    /// Value of the statement starting with the keyword, unquoted
+    fn value(&self, keyword: &str) -> Option<&str> {
+        self.statements.iter().find_map(|statement| {
+            let value = statement.strip_prefix(keyword)?.strip_prefix(' ')?;
+            Some(value.trim_matches('"'))
+        })
+    }
+
+    /// Nested blocks of the kind (the first word of their header), along
+    /// with the rest of their header
+    fn blocks<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = (&'a str, &'a IscBlock)> {
+        self.blocks.iter().filter_map(move |(header, block)| {
+            let (header_kind, rest) = header.split_once(' ').unwrap_or((header, ""));
+            (header_kind == kind).then_some((rest, block))
+        })
+    }
+
+    /// The lease blocks of the kind for the interface, the most recent
+    /// (last written) first. Blocks without an interface are taken as well.
+    fn leases<'a>(
+        &'a self,
+        kind: &'a str,
+        iface_name: &'a str,
+    ) -> impl Iterator<Item = &'a IscBlock> {
+        self.blocks(kind)
+            .map(|(_, block)| block)
+            .filter(move |block| {
+                block
+                    .value("interface")
+                    .is_none_or(|iface| iface == iface_name)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+    }
+
+    /// Has the lease expired? Leases that never expire, or whose expiry
+    /// time is unknown, have not.
+    fn expired(&self, now: u64) -> bool {
+        self.value("expire")
+            .and_then(parse_isc_time)
+            .is_some_and(|expire| expire <= now)
+    }
+}
+
+/// Parses a dhclient lease time: `<weekday> <yyyy/mm/dd> <hh:mm:ss>` (UTC)
+/// or `epoch <seconds>`, None for `never` and anything else
+fn parse_isc_time(value: &str) -> Option<u64> {
+    match value.split_whitespace().collect::<Vec<_>>()[..] {
+        ["epoch", secs, ..] => secs.parse().ok(),
+        [_, date, time, ..] => {
+            let time = format!("{}T{}Z", date.replace('/', "-"), time);
+            humantime::parse_rfc3339(&time)
+                .ok()?
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|since| since.as_secs())
+        }
+        _ => None,
+    }
+}
+
+/// Parses the lease files of ISC dhclient (`dhclient.leases.<iface>`),
+/// taking the most recent lease that has not expired
+pub struct IscLeaseSource;
+
+impl LeaseSource for IscLeaseSource {
+    fn lease(
+        &self,
+        iface_name: &str,
+        lease_file_path: &str,
+        content: &str,
+    ) -> Result<Option<Lease>, MonitorError> {
+        let Some(root) = IscBlock::parse(content) else {
+            return Err(incomplete(lease_file_path));
+        };
+        let mut leases = root
+            .leases("lease", iface_name)
+            .filter(|block| block.value("fixed-address").is_some())
+            .peekable();
+        // No lease at all yet, the file is being written
+        if leases.peek().is_none() {
+            return Err(incomplete(lease_file_path));
+        }
+
+        let now = unix_time();
+        let Some(block) = leases.find(|block| !block.expired(now)) else {
+            // Only expired leases, the lease is lost
+            return Ok(None);
+        };
+
+        let field = |keyword: &str| block.value(keyword).unwrap_or_default().to_owned();
+        Ok(Some(Lease {
+            ip_addr: parse_addr(lease_file_path, &field("fixed-address"))?,
+            router: parse_addr(lease_file_path, &first(&field("option routers"), ','))?,
+            next_server: field("next-server"),
+            filename: field("filename"),
+            host_name: field("option host-name"),
+            domain_name: field("option domain-name"),
+            dns_servers: parse_addrs(
+                lease_file_path,
+                &all(&field("option domain-name-servers"), ','),
+            )?,
+            lease_time: parse_lease_time(lease_file_path, &field("option dhcp-lease-time"))?,
+            expiry: block.value("expire").and_then(parse_isc_time),
+        }))
+    }
+
+    fn lease6(
+        &self,
+        iface_name: &str,
+        lease_file_path: &str,
+        content: &str,
+    ) -> Result<Option<Lease6>, MonitorError> {
+        let Some(root) = IscBlock::parse(content) else {
+            return Err(incomplete(lease_file_path));
+        };
+        let Some(block) = root.leases("lease6", iface_name).next() else {
+            return Err(incomplete(lease_file_path));
+        };
+
+        let ip6_prefixes: Vec<(String, String)> = block
+            .blocks("ia-pd")
+            .flat_map(|(_, ia_pd)| ia_pd.blocks("iaprefix"))
+            .filter_map(|(prefix, _)| {
+                let (ip_prefix, ip_prefix_len) = prefix.split_once('/')?;
+                Some((ip_prefix.to_owned(), ip_prefix_len.to_owned()))
+            })
+            .collect();
+        let ip6_addr = block
+            .blocks("ia-na")
+            .flat_map(|(_, ia_na)| ia_na.blocks("iaaddr"))
+            .map(|(ip_addr, _)| ip_addr.to_owned())
+            .next();
+
+        if ip6_prefixes.is_empty() && ip6_addr.is_none() {
+            return Err(incomplete(lease_file_path));
+        }
+        Ok(Some(Lease6 {
+            ip6_prefixes: parse_prefixes(lease_file_path, &ip6_prefixes)?,
+            ip6_addr: parse_addr(lease_file_path, &ip6_addr.unwrap_or_default())?,
+            dns_servers: parse_addrs(
+                lease_file_path,
+                &all(
+                    block.value("option dhcp6.name-servers").unwrap_or_default(),
+                    ',',
+                ),
+            )?,
+        }))
+    }
+}
+
+/// Extracts the delegated IPv6 prefixes (prefix, length) from the lease file
+pub fn get_lease_ip6_extract(content: &str) -> Vec<(String, String)> {
+    LEASE6_FIELD_IA_PD
+        .find_all(content)
+        .filter_map(|ia_pd| {
+            let cols: Vec<&str> = ia_pd.split_whitespace().collect();
+            match cols[..] {
+                [_, ip_prefix, ip_prefix_len, ..] => {
+                    Some((ip_prefix.to_string(), ip_prefix_len.to_string()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Extracts the assigned IPv6 address (ia_na) from the lease file
+pub fn get_lease_ip6_addr(content: &str) -> Option<String> {
+    LEASE6_FIELD_IA_NA.find(content).and_then(|ia_na| {
+        let cols: Vec<&str> = ia_na.split_whitespace().collect();
+        match cols[..] {
+            [_, ip_addr, ..] => Some(ip_addr.to_string()),
+            _ => None,
+        }
+    })
+}
+
+/// Parses the lease files of systemd-networkd
+/// (`/run/systemd/netif/leases/<ifindex>`), `KEY=value` lines. networkd
+/// does not keep DHCPv6 leases in files.
+pub struct NetworkdLeaseSource;
+
+impl LeaseSource for NetworkdLeaseSource {
+    fn lease(
+        &self,
+        _iface_name: &str,
+        lease_file_path: &str,
+        content: &str,
+    ) -> Result<Option<Lease>, MonitorError> {
+        let Some(ip_addr) = NETWORKD_FIELD_ADDRESS.find(content) else {
+            return Err(incomplete(lease_file_path));
+        };
+        let field = |field: &LeaseField| field.find(content).unwrap_or_default();
+
+        Ok(Some(Lease {
+            ip_addr: parse_addr(lease_file_path, &ip_addr)?,
+            router: parse_addr(lease_file_path, &first(&field(&NETWORKD_FIELD_ROUTER), ' '))?,
+            next_server: field(&NETWORKD_FIELD_NEXT_SERVER),
+            filename: String::new(),
+            host_name: field(&NETWORKD_FIELD_HOST_NAME),
+            domain_name: field(&NETWORKD_FIELD_DOMAIN_NAME),
+            dns_servers: parse_addrs(lease_file_path, &all(&field(&NETWORKD_FIELD_DNS), ' '))?,
+            lease_time: parse_lease_time(lease_file_path, &field(&NETWORKD_FIELD_LIFETIME))?,
+            expiry: None,
+        }))
+    }
+
+    fn lease6(
+        &self,
+        _iface_name: &str,
+        _lease_file_path: &str,
+        _content: &str,
+    ) -> Result<Option<Lease6>, MonitorError> {
+        Ok(None)
+    }
+}
+
+/// Gets the leases from `dhcpcd -U <iface>`, its lease files
+/// (`/var/db/dhcpcd/<iface>.lease`, `.lease6`) are raw DHCP messages
+pub struct DhcpcdLeaseSource {
+    dhcpcd_path: String,
+}
+
+impl DhcpcdLeaseSource {
+    pub fn new(dhcpcd_path: &str) -> Self {
+        Self {
+            dhcpcd_path: dhcpcd_path.to_owned(),
+        }
+    }
+
+    /// The interface's lease of the family (`-4` or `-6`) as dumped by
+    /// dhcpcd, `name='value'` lines
+    fn dump(
+        &self,
+        iface_name: &str,
+        family: &str,
+    ) -> Result<HashMap<String, String>, MonitorError> {
+        let lease_dump_error = |source| MonitorError::LeaseDump {
+            command: "dhcpcd",
+            source,
+        };
+        let output = Command::new(&self.dhcpcd_path)
+            .arg(family)
+            .arg("-U")
+            .arg(iface_name)
+            .output()
+            .map_err(lease_dump_error)?;
+
+        if !output.status.success() {
+            return Err(lease_dump_error(io::Error::other(format!(
+                "{}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))));
+        }
+
+        Ok(parse_dhcpcd_dump(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// The `name='value'` lines dumped by dhcpcd, unquoted
+fn parse_dhcpcd_dump(dump: &str) -> HashMap<String, String> {
+    FieldDelimiter::Equals
+        .fields(dump)
+        .map(|(name, value)| (name.to_owned(), unquote_shell(value)))
+        .collect()
+}
+
+/// Removes the shell quoting of a dhcpcd value: `'...'`, with `'\''` for
+/// a quote
+fn unquote_shell(value: &str) -> String {
+    value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+        .unwrap_or(value)
+        .replace("'\\''", "'")
+}
+
+impl LeaseSource for DhcpcdLeaseSource {
+    fn lease(
+        &self,
+        iface_name: &str,
+        lease_file_path: &str,
+        _content: &str,
+    ) -> Result<Option<Lease>, MonitorError> {
+        dhcpcd_lease(lease_file_path, &self.dump(iface_name, "-4")?)
+    }
+
+    fn lease6(
+        &self,
+        iface_name: &str,
+        lease_file_path: &str,
+        _content: &str,
+    ) -> Result<Option<Lease6>, MonitorError> {
+        dhcpcd_lease6(lease_file_path, &self.dump(iface_name, "-6")?)
+    }
+}
+
+/// The lease from the values dumped by dhcpcd
+fn dhcpcd_lease(
+    lease_file_path: &str,
+    values: &HashMap<String, String>,
+) -> Result<Option<Lease>, MonitorError> {
+    let field = |name: &str| values.get(name).cloned().unwrap_or_default();
+
+    // Without an ip_address the lease is lost
+    if !values.contains_key("ip_address") {
+        return Ok(None);
+    }
+    Ok(Some(Lease {
+        ip_addr: parse_addr(lease_file_path, &field("ip_address"))?,
+        router: parse_addr(lease_file_path, &first(&field("routers"), ' '))?,
+        next_server: field("next_server"),
+        filename: field("filename"),
+        host_name: field("host_name"),
+        domain_name: field("domain_name"),
+        dns_servers: parse_addrs(lease_file_path, &all(&field("domain_name_servers"), ' '))?,
+        lease_time: parse_lease_time(lease_file_path, &field("dhcp_lease_time"))?,
+        expiry: None,
+    }))
+}
+
+/// The lease (IPv6) from the values dumped by dhcpcd
+fn dhcpcd_lease6(
+    lease_file_path: &str,
+    values: &HashMap<String, String>,
+) -> Result<Option<Lease6>, MonitorError> {
+    // dhcp6_ia_pd<n>_prefix<m> and dhcp6_ia_pd<n>_prefix<m>_length
+    let mut ip6_prefixes: Vec<(&String, (String, String))> = values
+        .iter()
+        .filter(|(name, _)| name.starts_with("dhcp6_ia_pd") && !name.ends_with("_length"))
+        .filter(|(name, _)| name.contains("_prefix"))
+        .map(|(name, ip_prefix)| {
+            let ip_prefix_len = values
+                .get(&format!("{}_length", name))
+                .cloned()
+                .unwrap_or_default();
+            (name, (ip_prefix.to_owned(), ip_prefix_len))
+        })
+        .collect();
+    ip6_prefixes.sort();
+    // dhcp6_ia_na<n>_ia_addr<m>
+    let mut ip6_addrs: Vec<(&String, &String)> = values
+        .iter()
+        .filter(|(name, _)| name.starts_with("dhcp6_ia_na") && name.contains("_ia_addr"))
+        .collect();
+    ip6_addrs.sort();
+
+    // Without a prefix or an address the lease is lost
+    if ip6_prefixes.is_empty() && ip6_addrs.is_empty() {
+        return Ok(None);
+    }
+    let ip6_prefixes: Vec<(String, String)> = ip6_prefixes
+        .into_iter()
+        .map(|(_, ip6_prefix)| ip6_prefix)
+        .collect();
+    Ok(Some(Lease6 {
+        ip6_prefixes: parse_prefixes(lease_file_path, &ip6_prefixes)?,
+        ip6_addr: parse_addr(
+            lease_file_path,
+            ip6_addrs
+                .first()
+                .map(|(_, ip6_addr)| ip6_addr.as_str())
+                .unwrap_or_default(),
+        )?,
+        dns_servers: parse_addrs(
+            lease_file_path,
+            &all(
+                values
+                    .get("dhcp6_name_servers")
+                    .map(String::as_str)
+                    .unwrap_or_default(),
+                ' ',
+            ),
+        )?,
+    }))
+}
+
+/// All (non-empty) values in a list
+fn all(values: &str, separator: char) -> Vec<String> {
+    values
+        .split(separator)
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// First of the values in a list
+fn first(values: &str, separator: char) -> String {
+    values
+        .split(separator)
+        .map(str::trim)
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Path of an interface's lease file: the template, with `{iface}` replaced
+/// by the interface name and `{ifindex}` by its index, in the lease directory
+pub fn lease_file_path(lease_dir: &str, lease_file_template: &str, iface_name: &str) -> String {
+    let mut lease_file_name = lease_file_template.replace("{iface}", iface_name);
+    if lease_file_name.contains("{ifindex}") {
+        // No interface has index 0, a missing interface has no lease file
+        let iface_index = interface_index(iface_name).unwrap_or(0);
+        lease_file_name = lease_file_name.replace("{ifindex}", &iface_index.to_string());
+    }
+    format!("{lease_dir}/{lease_file_name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr<T: FromStr>(addr: &str) -> T
+    where
+        T::Err: fmt::Debug,
+    {
+        addr.parse().ok().unwrap()
+    }
+
+    #[test]
+    fn ip6_extract_takes_every_ia_pd() {
+        let content = "\
+ia_pd 0 2001:db8:1:: 48
+# ia_pd 0 2001:db8:ffff:: 48
+ia_pd 1 2001:db8:2:: 56
+";
+        assert_eq!(
+            get_lease_ip6_extract(content),
+            vec![
+                (String::from("2001:db8:1::"), String::from("48")),
+                (String::from("2001:db8:2::"), String::from("56")),
+            ]
+        );
+    }
+
+    #[test]
+    fn openbsd_commented_lease() {
+        let content = "\
+# updated by hand
+version: 2
+
+  # ip: 192.0.2.99
+ip: 192.0.2.10
+host-name:
+garbage without a delimiter
+:
+";
+        let lease = OpenbsdLeaseSource
+            .lease("em0", "em0", content)
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease.ip_addr, Some(addr("192.0.2.10")));
+        assert_eq!(lease.host_name, "");
+
+        let lease = OpenbsdLeaseSource.lease("em0", "em0", "# ip: 192.0.2.10\n\n");
+        assert!(matches!(
+            lease,
+            Err(MonitorError::IncompleteLeaseFile { .. })
+        ));
+    }
+
+    #[test]
+    fn openbsd_malformed_lease6() {
+        let content = "\
+# ia_pd 0 2001:db8:ffff:: 48
+ia_pd
+ia_pd 0
+ia_pd 0 2001:db8:1::
+ia_na
+ia_pd 0 2001:db8:2:: 56 extra
+";
+        let lease6 = OpenbsdLeaseSource
+            .lease6("em0", "em0", content)
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease6.ip6_prefixes, vec![(addr("2001:db8:2::"), 56)]);
+        assert_eq!(lease6.ip6_addr, None);
+
+        // Only malformed entries: nothing usable, as if being written
+        let lease6 = OpenbsdLeaseSource.lease6("em0", "em0", "ia_pd 0\nia_na\n");
+        assert!(matches!(
+            lease6,
+            Err(MonitorError::IncompleteLeaseFile { .. })
+        ));
+    }
+
+    #[test]
+    fn ip6_extract_skips_short_ia_pd() {
+        assert!(get_lease_ip6_extract("ia_pd 0 2001:db8:1::\n").is_empty());
+        assert!(get_lease_ip6_extract("ia_na 0 2001:db8::1\n").is_empty());
+    }
+
+    /// An ISC dhclient lease for em0 expiring at the given time
+    fn isc_lease(ip_addr: &str, expire: &str) -> String {
+        format!(
+            "lease {{
+  interface \"em0\";
+  fixed-address {ip_addr};
+  option routers 192.0.2.1;
+  option dhcp-lease-time 3600;
+  renew 4 2020/01/02 00:00:00;
+  expire {expire};
+}}
+"
+        )
+    }
+
+    #[test]
+    fn isc_takes_most_recent_lease() {
+        let content = isc_lease("192.0.2.10", "6 2099/01/03 00:00:00")
+            + &isc_lease("192.0.2.11", "6 2099/01/03 00:00:00");
+        let lease = IscLeaseSource
+            .lease("em0", "dhclient.leases.em0", &content)
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease.ip_addr, Some(addr("192.0.2.11")));
+        assert_eq!(lease.router, Some(addr("192.0.2.1")));
+        assert_eq!(lease.expiry, Some(4071081600));
+        assert_eq!(lease.lease_time, Some(3600));
+    }
+
+    #[test]
+    fn isc_skips_expired_leases() {
+        let content =
+            isc_lease("192.0.2.10", "never") + &isc_lease("192.0.2.11", "4 2020/01/02 00:00:00");
+        let lease = IscLeaseSource
+            .lease("em0", "dhclient.leases.em0", &content)
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease.ip_addr, Some(addr("192.0.2.10")));
+        assert_eq!(lease.expiry, None);
+    }
+
+    #[test]
+    fn isc_only_expired_leases_hold_no_lease() {
+        let content = isc_lease("192.0.2.10", "4 2020/01/02 00:00:00")
+            + &isc_lease("192.0.2.11", "epoch 1577923200");
+        let lease = IscLeaseSource
+            .lease("em0", "dhclient.leases.em0", &content)
+            .unwrap();
+        assert!(lease.is_none());
+    }
+
+    #[test]
+    fn isc_leases_of_other_interfaces_are_skipped() {
+        let content = isc_lease("192.0.2.10", "never").replace("em0", "em1");
+        let lease = IscLeaseSource.lease("em0", "dhclient.leases.em0", &content);
+        assert!(matches!(
+            lease,
+            Err(MonitorError::IncompleteLeaseFile { .. })
+        ));
+    }
+
+    #[test]
+    fn isc_unclosed_lease_is_incomplete() {
+        let content = isc_lease("192.0.2.10", "never");
+        let lease = IscLeaseSource.lease(
+            "em0",
+            "dhclient.leases.em0",
+            content.trim_end().trim_end_matches('}'),
+        );
+        assert!(matches!(
+            lease,
+            Err(MonitorError::IncompleteLeaseFile { .. })
+        ));
+    }
+
+    #[test]
+    fn isc_time_formats() {
+        assert_eq!(parse_isc_time("4 2020/01/02 00:00:00"), Some(1577923200));
+        assert_eq!(parse_isc_time("epoch 1577923200"), Some(1577923200));
+        assert_eq!(parse_isc_time("never"), None);
+    }
+
+    #[test]
+    fn openbsd_ia_na_only_lease() {
+        let lease6 = OpenbsdLeaseSource
+            .lease6("em0", "em0", "ia_na 0 2001:db8::10\n")
+            .unwrap()
+            .unwrap();
+        assert!(lease6.ip6_prefixes.is_empty());
+        assert_eq!(lease6.ip6_addr, Some(addr("2001:db8::10")));
+    }
+
+    #[test]
+    fn openbsd_name_servers() {
+        let content = "\
+version: 2
+ip: 192.0.2.10
+nameserver: 192.0.2.53
+nameserver: 192.0.2.54
+domain-name: example.org
+";
+        let lease = OpenbsdLeaseSource
+            .lease("em0", "em0", content)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            lease.dns_servers,
+            vec![addr::<Ipv4Addr>("192.0.2.53"), addr("192.0.2.54")]
+        );
+
+        let lease6 = OpenbsdLeaseSource
+            .lease6(
+                "em0",
+                "em0",
+                "ia_pd 0 2001:db8:1:: 48\nnameserver 2001:db8::53\n",
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease6.dns_servers, vec![addr::<Ipv6Addr>("2001:db8::53")]);
+
+        let lease = OpenbsdLeaseSource
+            .lease("em0", "em0", "ip: 192.0.2.10\n")
+            .unwrap()
+            .unwrap();
+        assert!(lease.dns_servers.is_empty());
+
+        let lease = OpenbsdLeaseSource.lease("em0", "em0", "ip: 192.0.2.10\nnameserver: dns\n");
+        assert!(matches!(lease, Err(MonitorError::InvalidLease { .. })));
+    }
+
+    #[test]
+    fn openbsd_lease6_without_ia_is_incomplete() {
+        let lease6 = OpenbsdLeaseSource.lease6("em0", "em0", "# written by dhcp6leased\n");
+        assert!(matches!(
+            lease6,
+            Err(MonitorError::IncompleteLeaseFile { .. })
+        ));
+    }
+
+    #[test]
+    fn isc_ia_na_only_lease() {
+        let content = "\
+lease6 {
+  interface \"em0\";
+  ia-na 1a:2b:3c:4d {
+    starts 1577923200;
+    renew 1800;
+    rebind 2880;
+    iaaddr 2001:db8::10 {
+      starts 1577923200;
+      preferred-life 3600;
+      max-life 7200;
+    }
+  }
+  option dhcp6.name-servers 2001:db8::53;
+}
+";
+        let lease6 = IscLeaseSource
+            .lease6("em0", "dhclient6.leases.em0", content)
+            .unwrap()
+            .unwrap();
+        assert!(lease6.ip6_prefixes.is_empty());
+        assert_eq!(lease6.ip6_addr, Some(addr("2001:db8::10")));
+        assert_eq!(lease6.dns_servers, vec![addr::<Ipv6Addr>("2001:db8::53")]);
+    }
+
+    #[test]
+    fn ia_na_only_lease_is_up() {
+        let lease6_params = Lease6Params {
+            iface_name: String::from("em0"),
+            ip6_addr: Some(addr("2001:db8::10")),
+            ..Default::default()
+        };
+        assert!(!lease6_params.is_down());
+        assert!(Lease6Params::down("em0").is_down());
+    }
+
+    #[test]
+    fn networkd_lease() {
+        let content = "\
+# This is private data. Do not parse.
+ADDRESS=192.0.2.10
+NETMASK=255.255.255.0
+ROUTER=192.0.2.1 192.0.2.2
+SERVER_ADDRESS=192.0.2.1
+DNS=192.0.2.53 192.0.2.54
+DOMAINNAME=example.org
+HOSTNAME=host
+LIFETIME=3600
+";
+        let lease = NetworkdLeaseSource
+            .lease("eth0", "2", content)
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease.ip_addr, Some(addr("192.0.2.10")));
+        assert_eq!(lease.router, Some(addr("192.0.2.1")));
+        assert_eq!(lease.domain_name, "example.org");
+        assert_eq!(lease.host_name, "host");
+        assert_eq!(lease.lease_time, Some(3600));
+        assert_eq!(
+            lease.dns_servers,
+            vec![addr::<Ipv4Addr>("192.0.2.53"), addr("192.0.2.54")]
+        );
+    }
+
+    #[test]
+    fn networkd_lease_without_address_is_incomplete() {
+        let lease =
+            NetworkdLeaseSource.lease("eth0", "2", "# ADDRESS=192.0.2.10\nROUTER=192.0.2.1\n");
+        assert!(matches!(
+            lease,
+            Err(MonitorError::IncompleteLeaseFile { .. })
+        ));
+    }
+
+    #[test]
+    fn dhcpcd_dumped_lease() {
+        let values = parse_dhcpcd_dump(
+            "\
+ip_address='192.0.2.10'
+routers='192.0.2.1 192.0.2.2'
+domain_name_servers='192.0.2.53 192.0.2.54'
+domain_name='example.org'
+dhcp_lease_time='86400'
+",
+        );
+        let lease = dhcpcd_lease("em0.lease", &values).unwrap().unwrap();
+        assert_eq!(lease.ip_addr, Some(addr("192.0.2.10")));
+        assert_eq!(lease.router, Some(addr("192.0.2.1")));
+        assert_eq!(lease.domain_name, "example.org");
+        assert_eq!(lease.lease_time, Some(86400));
+        assert_eq!(lease.dns_servers.len(), 2);
+
+        let values = parse_dhcpcd_dump("dhcp_lease_time='86400'\n");
+        assert!(dhcpcd_lease("em0.lease", &values).unwrap().is_none());
+    }
+
+    #[test]
+    fn dhcpcd_dumped_lease6() {
+        let values = parse_dhcpcd_dump(
+            "\
+dhcp6_ia_pd1_prefix2='2001:db8:2::'
+dhcp6_ia_pd1_prefix2_length='56'
+dhcp6_ia_pd1_prefix1='2001:db8:1::'
+dhcp6_ia_pd1_prefix1_length='48'
+dhcp6_ia_na1_ia_addr1='2001:db8::10'
+dhcp6_name_servers='2001:db8::53'
+",
+        );
+        let lease6 = dhcpcd_lease6("em0.lease6", &values).unwrap().unwrap();
+        assert_eq!(
+            lease6.ip6_prefixes,
+            vec![(addr("2001:db8:1::"), 48), (addr("2001:db8:2::"), 56)]
+        );
+        assert_eq!(lease6.ip6_addr, Some(addr("2001:db8::10")));
+        assert_eq!(lease6.dns_servers, vec![addr::<Ipv6Addr>("2001:db8::53")]);
+    }
+
+    #[test]
+    fn malformed_lease_time_is_invalid() {
+        let content = isc_lease("192.0.2.10", "never").replace("3600", "1h");
+        let lease = IscLeaseSource.lease("em0", "dhclient.leases.em0", &content);
+        assert!(matches!(
+            lease,
+            Err(MonitorError::InvalidLease { reason, .. }) if reason == "invalid lease time: 1h"
+        ));
+    }
+
+    #[test]
+    fn equals_fields_skip_comments() {
+        let fields: Vec<(&str, &str)> = FieldDelimiter::Equals
+            .fields("# comment\n\nip_address='192.0.2.10'\nno value\n")
+            .collect();
+        assert_eq!(fields, vec![("ip_address", "'192.0.2.10'")]);
+        assert_eq!(unquote_shell("'it'\\''s'"), "it's");
+    }
+
+    #[test]
+    fn malformed_address_is_invalid() {
+        let content = isc_lease("192.0.2.300", "never");
+        let lease = IscLeaseSource.lease("em0", "dhclient.leases.em0", &content);
+        assert!(matches!(
+            lease,
+            Err(MonitorError::InvalidLease { reason, .. }) if reason == "invalid address: 192.0.2.300"
+        ));
+
+        let lease = NetworkdLeaseSource.lease(
+            "eth0",
+            "2",
+            "ADDRESS=192.0.2.10\nDNS=192.0.2.53 2001:db8::53\n",
+        );
+        assert!(matches!(lease, Err(MonitorError::InvalidLease { .. })));
+
+        let lease6 = OpenbsdLeaseSource.lease6("em0", "em0", "ia_na 0 2001:db8::zz\n");
+        assert!(matches!(lease6, Err(MonitorError::InvalidLease { .. })));
+    }
+
+    #[test]
+    fn malformed_prefix_length_is_invalid() {
+        let lease6 = OpenbsdLeaseSource.lease6("em0", "em0", "ia_pd 0 2001:db8:1:: 129\n");
+        assert!(matches!(
+            lease6,
+            Err(MonitorError::InvalidLease { reason, .. }) if reason == "invalid prefix length: 129"
+        ));
+        let lease6 = OpenbsdLeaseSource.lease6("em0", "em0", "ia_pd 0 2001:db8:1:: 4x\n");
+        assert!(matches!(lease6, Err(MonitorError::InvalidLease { .. })));
+    }
+
+    #[test]
+    fn addresses_are_canonical() {
+        let lease6 = OpenbsdLeaseSource
+            .lease6("em0", "em0", "ia_pd 0 2001:0db8:0001:0000:: 48\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease6.ip6_prefixes, vec![(addr("2001:db8:1::"), 48)]);
+    }
+
+    #[test]
+    fn lease_params_serialize_as_strings() {
+        let lease_params = LeaseParams::down("em0");
+        let json = serde_json::to_value(&lease_params).unwrap();
+        assert_eq!(json["ip_addr"], "");
+        let parsed: LeaseParams = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.ip_addr, None);
+
+        let lease6_params = Lease6Params {
+            iface_name: String::from("em0"),
+            ip6_prefix: Some(addr("2001:db8:1::")),
+            ip6_prefix_len: Some(48),
+            ip6_prefixes: vec![(addr("2001:db8:1::"), 48)],
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&lease6_params).unwrap();
+        assert_eq!(json["ip6_prefix"], "2001:db8:1::");
+        assert_eq!(json["ip6_prefix_len"], "48");
+        assert_eq!(json["ip6_prefixes"][0][1], "48");
+        assert_eq!(lease6_params.ip6_prefix_cidr(), "2001:db8:1::/48");
+        let parsed: Lease6Params = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.ip6_prefixes, lease6_params.ip6_prefixes);
+    }
+
+    #[test]
+    fn lease_file_path_replaces_iface() {
+        assert_eq!(
+            lease_file_path("/var/db/dhcpleased", "{iface}", "em0"),
+            "/var/db/dhcpleased/em0"
+        );
+        assert_eq!(
+            lease_file_path("/var/lib/dhcp", "dhclient.leases.{iface}", "eth0"),
+            "/var/lib/dhcp/dhclient.leases.eth0"
+        );
+    }
+
+    #[test]
+    fn lease_file_path_of_missing_interface_has_index_0() {
+        assert_eq!(
+            lease_file_path("/run/systemd/netif/leases", "{ifindex}", "nosuchif0"),
+            "/run/systemd/netif/leases/0"
+        );
+    }
+}
+
+// EOF
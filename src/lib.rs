@@ -0,0 +1,97 @@
+//! Monitors the DHCP leases of network interfaces (dhcpleased and
+//! dhcp6leased lease files) and runs trigger scripts when they change
+
+pub mod args;
+pub mod config;
+pub mod control;
+pub mod history;
+pub mod lease;
+pub mod logger;
+pub mod metrics;
+pub mod monitor;
+pub mod mqtt;
+pub mod pidfile;
+pub mod route;
+pub mod sandbox;
+pub mod state;
+pub mod status;
+pub mod systemd;
+pub mod trigger;
+pub mod webhook;
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+/// Is the binary there to be run, i.e. is the path (or the bare name found
+/// in PATH) an executable file? For netstat and dhcpcd.
+pub fn executable_exists(binary_path: &str) -> bool {
+    let is_executable = |path: &Path| {
+        fs::metadata(path)
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    };
+    if binary_path.contains('/') {
+        return is_executable(Path::new(binary_path));
+    }
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|dir| is_executable(&dir.join(binary_path)))
+    })
+}
+
+/// Can the user (uid, gid) create and remove files in the directory of the
+/// path, judging by the directory's owner and mode? For the files created
+/// before dropping privileges and removed on shutdown.
+pub fn dir_writable_by(path: &str, uid: libc::uid_t, gid: libc::gid_t) -> bool {
+    let dir = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(metadata) = fs::metadata(dir) else {
+        return false;
+    };
+    let mode = metadata.mode();
+    if uid == 0 {
+        true
+    } else if metadata.uid() == uid {
+        mode & 0o200 != 0
+    } else if metadata.gid() == gid {
+        mode & 0o020 != 0
+    } else {
+        mode & 0o002 != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dir_writable_by_checks_the_mode() {
+        let root = fs::metadata("/").unwrap();
+        assert!(dir_writable_by("/dhcpleasemon.pid", 0, 0));
+        if root.mode() & 0o002 == 0 {
+            assert!(!dir_writable_by(
+                "/dhcpleasemon.pid",
+                root.uid() + 1,
+                root.gid() + 1
+            ));
+        }
+        assert!(!dir_writable_by(
+            "/nonexistent/dhcpleasemon.pid",
+            1000,
+            1000
+        ));
+    }
+}
+
+// EOF
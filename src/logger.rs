@@ -0,0 +1,116 @@
+//! Log backend: stderr until the daemon detaches, syslog afterwards
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::env;
+use std::process;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use syslog::{Facility, Formatter3164, LoggerBackend};
+
+type Syslog = syslog::Logger<LoggerBackend, Formatter3164>;
+
+struct State {
+    /// Connection to syslogd, opened early (before chrooting)
+    syslog: Option<Syslog>,
+    /// Log to syslog instead of stderr
+    use_syslog: bool,
+    /// Color the stderr messages by level
+    color: bool,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    syslog: None,
+    use_syslog: false,
+    color: false,
+});
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        let use_syslog = state.use_syslog;
+        match state.syslog.as_mut() {
+            Some(syslog) if use_syslog => {
+                let message = record.args().to_string();
+                let _ = match record.level() {
+                    Level::Error => syslog.err(message),
+                    Level::Warn => syslog.warning(message),
+                    Level::Info => syslog.info(message),
+                    Level::Debug | Level::Trace => syslog.debug(message),
+                };
+            }
+            _ => {
+                let timestamp = humantime::format_rfc3339_millis(SystemTime::now());
+                if state.color {
+                    eprintln!(
+                        "{} \x1b[{}m{}\x1b[0m: {}",
+                        timestamp,
+                        level_color(record.level()),
+                        record.level(),
+                        record.args()
+                    );
+                } else {
+                    eprintln!("{} {}: {}", timestamp, record.level(), record.args());
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// ANSI color (SGR parameters) of the level
+fn level_color(level: Level) -> &'static str {
+    match level {
+        Level::Error => "1;31",
+        Level::Warn => "33",
+        Level::Info => "32",
+        Level::Debug => "36",
+        Level::Trace => "2",
+    }
+}
+
+/// Installs the logger, logging to stderr; colored if wanted, unless
+/// stderr is not a terminal or `NO_COLOR` is set
+pub fn init(level: LevelFilter, color: bool) {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let is_tty = unsafe { libc::isatty(libc::STDERR_FILENO) } == 1;
+    STATE.lock().unwrap_or_else(|e| e.into_inner()).color = color && !no_color && is_tty;
+
+    if log::set_logger(&Logger).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Connects to syslogd; needs to happen while its socket is reachable
+pub fn open_syslog() -> syslog::Result<()> {
+    let formatter = Formatter3164 {
+        facility: Facility::LOG_DAEMON,
+        hostname: None,
+        process: String::from(env!("CARGO_PKG_NAME")),
+        pid: process::id(),
+    };
+    let syslog = syslog::unix(formatter)?;
+    STATE.lock().unwrap_or_else(|e| e.into_inner()).syslog = Some(syslog);
+    Ok(())
+}
+
+/// Switches to syslog (if connected) once the daemon has detached
+pub fn use_syslog() {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(syslog) = state.syslog.as_mut() {
+        syslog.formatter.pid = process::id();
+        state.use_syslog = true;
+    }
+}
+
+// EOF
@@ -1,430 +1,424 @@
 use clap::Parser;
 use daemonize::Daemonize;
-use std::collections::HashMap;
+use dhcpleasemon::args::{Action, Args};
+use dhcpleasemon::config::Config;
+use dhcpleasemon::lease::LeaseFormat;
+use dhcpleasemon::monitor::Monitor;
+use dhcpleasemon::pidfile::PidFile;
+use dhcpleasemon::{
+    control, dir_writable_by, executable_exists, logger, metrics, route, sandbox, state, systemd,
+};
+use log::{error, warn};
+use std::env;
+use std::ffi::CString;
 use std::fs;
-use std::fs::File;
-use std::io::{self, BufRead};
-use std::path::Path;
-use std::process::Command;
-use std::thread::sleep;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-
-#[derive(Parser, Debug, Clone)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// Run in foreground
-    #[arg(short, long)]
-    foreground: bool,
-
-    /// PID file
-    #[arg(short, long, default_value = "/var/run/dhcpleasemon.pid")]
-    pid_file: String,
-
-    /// Root directory
-    #[arg(short, long, default_value = "/")]
-    root_dir: String,
-
-    /// Directory with trigger scripts
-    #[arg(short, long, default_value = "/etc/dhcpleasemon")]
-    scripts_dir: String,
-
-    /// Name prefix for trigger scripts (IPv4)
-    #[arg(long, default_value = "lease_trigger_")]
-    trigger_script_prefix: String,
-
-    /// Name prefix for trigger scripts (IPv6)
-    #[arg(long, default_value = "lease_trigger_")]
-    trigger_script_prefix_ipv6: String,
-
-    /// Directory monitored for lease changes
-    #[arg(short, long, default_value = "/var/db/dhcpleased")]
-    dhcp_lease_dir: String,
-
-    /// Directory monitored for IPv6 lease changes
-    #[arg(short, long, default_value = "/var/db/dhcp6leased")]
-    dhcp6_lease_dir: String,
-
-    /// Scan interval
-    #[arg(short = 't', long, default_value_t = 1)]
-    interval: u8,
-
-    /// Interfaces to monitor
-    #[arg(short, long)]
-    interfaces: Vec<String>,
-
-    /// Monitor IPv6 leases as well
-    #[arg(short = '6', long)]
-    ipv6: bool,
-
-    /// Verbosity
-    #[arg(short, long)]
-    verbosity: bool,
-}
+use std::io;
+use std::os::unix::fs::chroot;
+use std::process;
+
+/// Resolves a user name (or numeric uid) to its uid and primary gid
+fn lookup_user(name: &str) -> Option<(libc::uid_t, libc::gid_t)> {
+    let c_name = CString::new(name).ok()?;
+    let passwd = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if !passwd.is_null() {
+        return Some(unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) });
+    }
 
-#[derive(PartialEq, Debug)]
-struct LeaseParams {
-    iface_name: String,
-    ip_addr: String,
-    route_addr: String,
+    let uid = name.parse().ok()?;
+    let passwd = unsafe { libc::getpwuid(uid) };
+    if passwd.is_null() {
+        return None;
+    }
+    Some(unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) })
 }
 
-#[derive(PartialEq, Debug)]
-struct Lease6Params {
-    iface_name: String,
-    ip6_prefix: String,
-    ip6_prefix_len: String,
-    route6_addr: String,
+/// Resolves a group name (or numeric gid) to its gid
+fn lookup_group(name: &str) -> Option<libc::gid_t> {
+    let c_name = CString::new(name).ok()?;
+    let group = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if !group.is_null() {
+        return Some(unsafe { (*group).gr_gid });
+    }
+    name.parse().ok()
 }
 
-struct Monitor {
-    args: Args,
-    timestamps: HashMap<String, SystemTime>,
-    lease_params: HashMap<String, LeaseParams>,
-    lease6_params: HashMap<String, Lease6Params>,
-}
+/// Probes the setup for `--check`, logging every problem; returns whether
+/// none of them would stop the daemon
+fn check_setup(config: &Config, monitor: &mut Monitor) -> bool {
+    let mut ok = true;
 
-impl Monitor {
-    fn new(args: Args) -> Self {
-        Self {
-            args,
-            timestamps: HashMap::new(),
-            lease_params: HashMap::new(),
-            lease6_params: HashMap::new(),
-        }
+    if config.interfaces.is_empty() && !config.auto_discover {
+        error!("No interfaces to monitor");
+        ok = false;
     }
 
-    /// Was the file modified since the last check?
-    fn check_file_modified(&mut self, lease_file_path: &str) -> bool {
-        let metadata = fs::metadata(&lease_file_path);
-        let current_timestamp = metadata
-            .expect("Unsupported platform")
-            .modified()
-            .expect("Error getting modification timestamp");
+    config.check_written_paths();
+    ok &= monitor.check_lease_dirs() || !config.require_lease_dirs;
+    ok &= monitor.check_scripts() || !config.require_scripts;
 
-        let last_timestamp = self
-            .timestamps
-            .get(lease_file_path)
-            .copied()
-            .unwrap_or(SystemTime::from(UNIX_EPOCH));
+    if route::uses_netstat(config.route_lookup) && !executable_exists(&config.netstat_path) {
+        error!(
+            "netstat not found: {} (see --netstat-path)",
+            config.netstat_path
+        );
+        ok = false;
+    }
 
-        if current_timestamp > last_timestamp {
-            // Store the new timestamp
-            self.timestamps
-                .insert(lease_file_path.to_string(), current_timestamp);
+    if config.lease_format == LeaseFormat::Dhcpcd && !executable_exists(&config.dhcpcd_path) {
+        error!(
+            "dhcpcd not found: {} (see --dhcpcd-path)",
+            config.dhcpcd_path
+        );
+        ok = false;
+    }
 
-            return true;
+    if !config.foreground {
+        if let Err(e) = PidFile::check_dir(&config.pid_file) {
+            error!("{}", e);
+            ok = false;
         }
-
-        false
     }
 
-    /// Generates the lease file path for a given interface
-    fn get_lease_file_path(&self, iface_name: &str) -> String {
-        let dhcp_lease_dir = &self.args.dhcp_lease_dir;
-        format!("{dhcp_lease_dir}/{iface_name}")
-    }
+    ok
+}
 
-    /// Generates the lease (IPv6) file path for a given interface
-    fn get_lease6_file_path(&self, iface_name: &str) -> String {
-        let dhcp6_lease_dir = &self.args.dhcp6_lease_dir;
-        format!("{dhcp6_lease_dir}/{iface_name}")
+/// Switches to the given uid/gid, clearing the supplementary groups
+fn drop_privileges(uid: Option<libc::uid_t>, gid: Option<libc::gid_t>) -> io::Result<()> {
+    if let Some(gid) = gid {
+        if unsafe { libc::setgroups(0, std::ptr::null()) } != 0 || unsafe { libc::setgid(gid) } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
     }
 
-    /// Generates the trigger script path for a given interface
-    fn get_trigger_script_path(&self, iface_name: &str) -> String {
-        let trigger_scripts_path = &self.args.scripts_dir;
-        let trigger_scripts_prefix = &self.args.trigger_script_prefix;
-        format!("{trigger_scripts_path}/{trigger_scripts_prefix}{iface_name}")
+    if let Some(uid) = uid {
+        if unsafe { libc::setuid(uid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::getuid() != uid || libc::geteuid() != uid } {
+            return Err(io::Error::other("uid did not change"));
+        }
     }
 
-    /// Generates the (IPv6) trigger script path for a given interface
-    fn get_trigger_script_path_ipv6(&self, iface_name: &str) -> String {
-        let trigger_scripts_path = &self.args.scripts_dir;
-        let trigger_scripts_prefix = &self.args.trigger_script_prefix_ipv6;
-        format!("{trigger_scripts_path}/{trigger_scripts_prefix}{iface_name}")
-    }
+    Ok(())
+}
 
-    /// Gets the default route for iface from netstat
-    fn get_default_route(&self, iface_name: &str, proto: &str) -> Option<String> {
-        let output = Command::new("netstat")
-            .arg("-rn")
-            .arg("-f")
-            .arg(proto)
-            .output()
-            .expect("Failed to execute netstat");
-
-        if !output.status.success() {
-            println!(
-                "Failed to obtain route (iface: {}): {}",
-                iface_name,
-                output.status.to_string()
-            );
-            return None;
+fn main() {
+    let args = Args::parse();
+    let mut config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
         }
+    };
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-
-        for line in stdout.lines() {
-            let cols: Vec<&str> = line.split_whitespace().collect();
-            if cols.len() == 8 {
-                let route_iface = cols[7];
-                let route_dest = cols[0];
-                if route_iface == iface_name && route_dest == "default" {
-                    let route_ip = cols[1];
-                    return Some(route_ip.to_string());
-                }
+    if args.print_config {
+        match config.to_toml() {
+            Ok(toml) => print!("{}", toml),
+            Err(e) => {
+                eprintln!("Error: Failed to print the configuration: {}", e);
+                process::exit(1);
             }
         }
+        process::exit(0);
+    }
 
-        return None;
+    // A single scan is run in the foreground
+    config.foreground |= args.oneshot;
+
+    if let Some(Action::Status { iface }) = &args.action {
+        let Some(control_socket) = &config.control_socket else {
+            eprintln!("Error: No control socket configured (--control-socket)");
+            process::exit(1);
+        };
+        process::exit(control::print_status(control_socket, iface.as_deref()));
     }
 
-    /// Extracts the IPv4 address from the lease file
-    fn get_lease_ip4_addr(&self, lease_file_path: &str) -> Option<String> {
-        if let Ok(f) = File::open(lease_file_path) {
-            let lines = io::BufReader::new(f).lines();
-            for line in lines.flatten() {
-                if let Some((ident, value)) = line.split_once(":") {
-                    if ident.trim() == "ip" {
-                        return Some(value.trim().to_string());
-                    }
-                }
-            }
-        }
-        None
+    if let Some(Action::History { iface }) = &args.action {
+        let Some(control_socket) = &config.control_socket else {
+            eprintln!("Error: No control socket configured (--control-socket)");
+            process::exit(1);
+        };
+        process::exit(control::print_history(control_socket, iface.as_deref()));
     }
 
-    /// Extract the IPv6 address from the lease file
-    fn get_lease_ip6_extract(&self, lease_file_path: &str) -> Option<(String, String)> {
-        if let Ok(f) = File::open(lease_file_path) {
-            let lines = io::BufReader::new(f).lines();
-            for line in lines.flatten() {
-                let cols: Vec<&str> = line.split_whitespace().collect();
-                if cols.len() > 0 {
-                    let root_directive = cols[0].trim().to_string();
-                    if root_directive == "ia_pd" {
-                        let ip_prefix = cols[2].trim().to_string();
-                        let ip_prefix_len = cols[3].trim().to_string();
-                        return Some((ip_prefix, ip_prefix_len));
-                    }
-                }
-            }
+    logger::init(config.log_level(), !config.no_color);
+
+    if !config.foreground && !args.check {
+        // Connect before chrooting, the syslog socket is unreachable after
+        if let Err(e) = logger::open_syslog() {
+            warn!("Failed to connect to syslog, logging to stderr: {}", e);
         }
-        None
     }
 
-    /// Execute the trigger script
-    fn run_trigger_script(&mut self, lease_params: &LeaseParams) -> () {
-        let iface_name = lease_params.iface_name.to_owned();
-        let trigger_script_path = self.get_trigger_script_path(&iface_name);
-
-        if !Path::new(&trigger_script_path).exists() {
-            return;
+    if config.journal {
+        // Connect before chrooting, like syslog
+        if let Err(e) = systemd::open_journal() {
+            warn!(
+                "Failed to connect to the journal, logging triggers as usual: {}",
+                e
+            );
         }
+    }
 
-        let default_route = lease_params.route_addr.to_owned();
-        let lease_ip_addr = lease_params.ip_addr.to_owned();
+    let mut monitor = Monitor::new(config.clone());
 
-        if self.verbosity() {
-            println!("Triggered: {:?}", lease_params);
+    if config.interfaces.is_empty() && !config.auto_discover {
+        error!("No interfaces to monitor");
+        process::exit(1);
+    }
+
+    // Resolve the ids before chrooting, the user database may be unreachable after
+    let (uid, mut gid) = match &config.user {
+        Some(user) => match lookup_user(user) {
+            Some((uid, gid)) => (Some(uid), Some(gid)),
+            None => {
+                error!("Unknown user {}", user);
+                process::exit(1);
+            }
+        },
+        None => (None, None),
+    };
+
+    if let Some(group) = &config.group {
+        match lookup_group(group) {
+            Some(group_gid) => gid = Some(group_gid),
+            None => {
+                error!("Unknown group {}", group);
+                process::exit(1);
+            }
         }
+    }
 
-        let output = Command::new(&trigger_script_path)
-            .env("DHCP_IFACE", iface_name)
-            .env("DHCP_IP_ADDR", lease_ip_addr)
-            .env("DHCP_IP_ROUTE", default_route)
-            .output()
-            .expect("Failed to execute trigger script");
+    if config.root_dir != "/" {
+        if let Err(e) = chroot(&config.root_dir).and_then(|_| env::set_current_dir("/")) {
+            error!("Failed to chroot to {}: {}", config.root_dir, e);
+            process::exit(1);
+        }
+    }
 
-        if !output.status.success() {
-            println!(
-                "Trigger script execution was unsuccessful: {} (path: {})",
-                output.status.to_string(),
-                &trigger_script_path,
-            );
+    if args.check {
+        if !check_setup(&config, &mut monitor) {
+            error!("Check failed");
+            process::exit(1);
         }
+        println!("Check passed");
+        process::exit(0);
     }
 
-    fn run_trigger_script_ipv6(&mut self, lease_params: &Lease6Params) -> () {
-        let iface_name = lease_params.iface_name.to_owned();
-        let trigger_script_path = self.get_trigger_script_path_ipv6(&iface_name);
+    // Kept locked until shutdown
+    let pid_file = if !config.foreground {
+        // Locked before daemonizing, so that a second instance fails right away
+        let mut pid_file = match PidFile::lock(&config.pid_file) {
+            Ok(pid_file) => pid_file,
+            Err(e) => {
+                error!("{}", e);
+                process::exit(1);
+            }
+        };
 
-        if !Path::new(&trigger_script_path).exists() {
-            return;
+        match Daemonize::new().start() {
+            Ok(_) => {}
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
         }
 
-        let default_route = lease_params.route6_addr.to_owned();
-        let lease_ip_prefix = lease_params.ip6_prefix.to_owned();
-        let lease_ip_prefix_len = lease_params.ip6_prefix_len.to_owned();
+        logger::use_syslog();
 
-        if self.verbosity() {
-            println!("Triggered: {:?}", lease_params);
+        if let Err(e) = pid_file.write_pid() {
+            error!("{}", e);
+            process::exit(1);
         }
+        Some(pid_file)
+    } else {
+        None
+    };
 
-        let output = Command::new(&trigger_script_path)
-            .env("DHCP6_IFACE", iface_name)
-            .env("DHCP6_IP_PREFIX", lease_ip_prefix)
-            .env("DHCP6_IP_PREFIX_LEN", lease_ip_prefix_len)
-            .env("DHCP6_IP_ROUTE", default_route)
-            .output()
-            .expect("Failed to execute trigger script");
-
-        if !output.status.success() {
-            println!(
-                "Trigger script execution was unsuccessful: {} (path: {})",
-                output.status.to_string(),
-                &trigger_script_path,
+    if let (false, Some(control_socket)) = (args.oneshot, &config.control_socket) {
+        if let Err(e) = control::serve(control_socket, monitor.status(), monitor.history()) {
+            error!(
+                "Failed to listen on control socket {}: {}",
+                control_socket, e
             );
+            process::exit(1);
         }
     }
 
-    /// Gathers all params related to the lease associated with an interface
-    fn get_actual_lease_params(&self, iface_name: &str) -> LeaseParams {
-        let lease_file_path = self.get_lease_file_path(&iface_name);
-        LeaseParams {
-            iface_name: iface_name.to_string(),
-            ip_addr: self
-                .get_lease_ip4_addr(&lease_file_path)
-                .unwrap_or(String::from("")),
-            route_addr: self
-                .get_default_route(&iface_name, "inet")
-                .unwrap_or(String::from("")),
+    // Listen before dropping privileges, the port may be privileged
+    if let Some(metrics_listen) = &config.metrics_listen {
+        if let Err(e) = metrics::serve(metrics_listen, monitor.metrics()) {
+            error!("Failed to serve metrics on {}: {}", metrics_listen, e);
+            process::exit(1);
         }
     }
 
-    /// Gathers all params related to the lease associated with an interface
-    fn get_actual_lease6_params(&self, iface_name: &str) -> Lease6Params {
-        let lease_file_path = self.get_lease6_file_path(&iface_name);
-        let (ip6_prefix, ip6_prefix_len) = self
-            .get_lease_ip6_extract(&lease_file_path)
-            .unwrap_or((String::from(""), String::from("")));
-        let route6_addr = self
-            .get_default_route(&iface_name, "inet6")
-            .unwrap_or(String::from(""));
-
-        Lease6Params {
-            iface_name: iface_name.to_string(),
-            ip6_prefix,
-            ip6_prefix_len,
-            route6_addr,
+    // Removed on shutdown, as the user (see the README, Dropping privileges)
+    if let Some(uid) = uid {
+        let gid = gid.unwrap_or(unsafe { libc::getgid() });
+        if let Some(pid_file) = &pid_file {
+            if let Err(e) = pid_file.chown(Some(uid), Some(gid)) {
+                warn!("{}", e);
+            }
         }
-    }
-
-    fn check_lease(&mut self, iface_name: &str) {
-        if self.verbosity() {
-            println!("Checking (IPv4): {}", iface_name);
-        }
-
-        let lease_file_path = self.get_lease_file_path(&iface_name);
-        if self.check_file_modified(&lease_file_path) {
-            let lease_params = self.get_actual_lease_params(&iface_name);
-
-            let trigger = match self.lease_params.get(iface_name) {
-                Some(current_lease_params) => {
-                    if *current_lease_params != lease_params {
-                        true
-                    } else {
-                        if self.verbosity() {
-                            println!("Lease params unchanged: {:?}", lease_params);
-                        }
-                        false
-                    }
-                }
-                None => true,
-            };
-
-            if trigger {
-                if self.verbosity() {
-                    println!("Triggered: {:?}", lease_params);
-                }
-                self.run_trigger_script(&lease_params);
-                self.lease_params
-                    .insert(iface_name.to_owned(), lease_params);
+        let control_socket = config.control_socket.as_ref().filter(|_| !args.oneshot);
+        if let Some(control_socket) = control_socket {
+            if let Err(e) = std::os::unix::fs::chown(control_socket, Some(uid), Some(gid)) {
+                warn!(
+                    "Failed to hand control socket {} over: {}",
+                    control_socket, e
+                );
             }
-        } else {
-            if self.verbosity() {
-                println!("File not modified for {}", iface_name);
+        }
+        let removed_files = pid_file
+            .as_ref()
+            .map(|_| (&config.pid_file, "PID file"))
+            .into_iter()
+            .chain(control_socket.map(|path| (path, "control socket")));
+        for (path, what) in removed_files {
+            if !dir_writable_by(path, uid, gid) {
+                warn!(
+                    "The directory of the {} {} is not writable by {}, it is left behind on shutdown",
+                    what,
+                    path,
+                    config.user.as_deref().unwrap_or_default()
+                );
             }
         }
     }
 
-    fn check_lease6(&mut self, iface_name: &str) {
-        if self.verbosity() {
-            println!("Checking (IPv6): {}", iface_name);
-        }
-
-        let lease_file_path = self.get_lease6_file_path(&iface_name);
-        if self.check_file_modified(&lease_file_path) {
-            let lease6_params = self.get_actual_lease6_params(&iface_name);
-
-            let trigger = match self.lease6_params.get(iface_name) {
-                Some(current_lease6_params) => {
-                    if *current_lease6_params != lease6_params {
-                        true
-                    } else {
-                        if self.verbosity() {
-                            println!("Lease params unchanged: {:?}", lease6_params);
-                        }
-                        false
-                    }
-                }
-                None => true,
-            };
-
-            if trigger {
-                if self.verbosity() {
-                    println!("Triggered: {:?}", lease6_params);
-                }
-                self.run_trigger_script_ipv6(&lease6_params);
-                self.lease6_params
-                    .insert(iface_name.to_owned(), lease6_params);
-            }
-        } else {
-            if self.verbosity() {
-                println!("File not modified for {}", iface_name);
-            }
-        }
+    if let Err(e) = drop_privileges(uid, gid) {
+        error!("Failed to drop privileges: {}", e);
+        process::exit(1);
     }
 
-    /// The main monitoring loop
-    fn run(&mut self) {
-        loop {
-            for iface_name in self.args.interfaces.clone() {
-                self.check_lease(&iface_name);
-                if self.args.ipv6 {
-                    self.check_lease6(&iface_name);
-                }
-            }
-            sleep(Duration::new(self.args.interval.into(), 0));
+    if let (false, Some(init_script)) = (args.oneshot, &config.init_script) {
+        if !monitor.run_hook_script(init_script, "init", &[]) && config.init_script_required {
+            error!("Init script failed, aborting startup");
+            process::exit(1);
         }
     }
 
-    fn verbosity(&self) -> bool {
-        self.args.verbosity
+    if let Err(e) = monitor.register_signals() {
+        error!("Failed to register signal handlers: {}", e);
+        process::exit(1);
     }
-}
 
-fn main() {
-    let args = Args::parse();
-    let mut monitor = Monitor::new(args.clone());
+    if !monitor.check_scripts() && config.require_scripts {
+        error!("Trigger scripts missing or not executable, aborting startup");
+        process::exit(1);
+    }
 
-    if args.interfaces.is_empty() {
-        panic!("No interfaces to monitor");
+    config.check_written_paths();
+    if !monitor.check_lease_dirs() && config.require_lease_dirs {
+        error!("Lease directories missing or not readable, aborting startup");
+        process::exit(1);
     }
 
-    if !args.foreground {
-        let daemonize = Daemonize::new().pid_file(&args.pid_file);
+    monitor.load_state();
 
-        match daemonize.start() {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("Error: {}", e);
-                return;
+    if !config.no_sandbox {
+        let mut unveil_paths = vec![
+            (config.dhcp_lease_dir.as_str(), "r"),
+            (config.scripts_dir.as_str(), "rx"),
+            (config.netstat_path.as_str(), "x"),
+        ];
+        if config.ipv6 {
+            unveil_paths.push((config.dhcp6_lease_dir.as_str(), "r"));
+        }
+        for extra in &config.extra_lease_dir {
+            if extra.proto == "inet" || config.ipv6 {
+                unveil_paths.push((extra.dir.as_str(), "r"));
             }
         }
+        if config.lease_format == LeaseFormat::Dhcpcd {
+            unveil_paths.push((config.dhcpcd_path.as_str(), "x"));
+        }
+        for iface in &config.interfaces {
+            // The relative ones are in the scripts directory
+            for script in [&iface.trigger_script, &iface.trigger_script_ipv6]
+                .into_iter()
+                .flatten()
+                .filter(|script| script.starts_with('/'))
+            {
+                unveil_paths.push((script.as_str(), "rx"));
+            }
+            if let Some(dhcp_lease_dir) = &iface.dhcp_lease_dir {
+                unveil_paths.push((dhcp_lease_dir.as_str(), "r"));
+            }
+            if let (true, Some(dhcp6_lease_dir)) = (config.ipv6, &iface.dhcp6_lease_dir) {
+                unveil_paths.push((dhcp6_lease_dir.as_str(), "r"));
+            }
+        }
+        if let Some(shared_script) = &config.shared_script {
+            unveil_paths.push((shared_script.as_str(), "rx"));
+        }
+        for hook_script in [&config.cleanup_script, &config.pre_hook, &config.post_hook]
+            .into_iter()
+            .flatten()
+        {
+            unveil_paths.push((hook_script.as_str(), "x"));
+        }
+        if !config.foreground {
+            unveil_paths.push((config.pid_file.as_str(), "c"));
+        }
+        // Read again on SIGHUP
+        if let Some(config_path) = &args.config {
+            unveil_paths.push((config_path.as_str(), "r"));
+        }
+        let mut promises = String::from("stdio rpath cpath proc exec route");
+        if let Some(control_socket) = &config.control_socket {
+            unveil_paths.push((control_socket.as_str(), "c"));
+            promises.push_str(" unix");
+        }
+        if config.webhook_url.is_some() || config.mqtt_broker.is_some() {
+            unveil_paths.push(("/etc/resolv.conf", "r"));
+            unveil_paths.push(("/etc/hosts", "r"));
+            promises.push_str(" inet dns");
+        } else if config.metrics_listen.is_some() {
+            promises.push_str(" inet");
+        }
+        // Both are replaced by renaming a temporary file
+        let written_files: Vec<(&str, String)> = [&config.state_file, &config.status_file]
+            .into_iter()
+            .flatten()
+            .map(|path| (path.as_str(), state::tmp_path(path)))
+            .collect();
+        for (path, tmp_path) in &written_files {
+            unveil_paths.push((path, "rwc"));
+            unveil_paths.push((tmp_path.as_str(), "rwc"));
+        }
+        if !written_files.is_empty() {
+            promises.push_str(" wpath");
+        }
+
+        if let Err(e) = sandbox::unveil(&unveil_paths).and_then(|_| sandbox::pledge(&promises)) {
+            error!("Failed to set up the sandbox: {}", e);
+            process::exit(1);
+        }
+    }
+
+    if args.oneshot {
+        process::exit(monitor.run_once());
     }
 
     monitor.run();
+
+    if let Some(cleanup_script) = &config.cleanup_script {
+        monitor.run_hook_script(cleanup_script, "cleanup", &[]);
+    }
+
+    if let Some(control_socket) = &config.control_socket {
+        if let Err(e) = fs::remove_file(control_socket) {
+            error!("Failed to remove control socket {}: {}", control_socket, e);
+        }
+    }
+
+    if let Some(pid_file) = pid_file {
+        pid_file.remove();
+    }
 }
 
 // EOF
@@ -0,0 +1,372 @@
+//! Prometheus metrics, served over HTTP by a thread of their own
+//! (`--metrics-listen`)
+
+use crate::unix_time;
+use log::{debug, error};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Number of recent scans the scan duration statistics are taken over
+const SCAN_DURATION_WINDOW: usize = 100;
+
+/// Upper bounds (seconds) of the trigger duration histogram buckets, the
+/// last bucket (`+Inf`) takes the longer ones
+const TRIGGER_DURATION_BUCKETS: [f64; 4] = [0.1, 1.0, 5.0, 30.0];
+
+/// Counters and gauges, updated by the monitor and the trigger workers
+#[derive(Default)]
+pub struct Metrics {
+    data: Mutex<MetricsData>,
+}
+
+#[derive(Default)]
+struct MetricsData {
+    triggers: BTreeMap<String, u64>,
+    trigger_failures: BTreeMap<String, u64>,
+    /// 1 while the interface's last trigger failed (after its retries)
+    interface_failed: BTreeMap<String, u64>,
+    last_change: BTreeMap<String, u64>,
+    lease_errors: BTreeMap<String, u64>,
+    /// Trigger script run durations by family (`inet`, `inet6`, `dual`)
+    trigger_durations: BTreeMap<String, Histogram>,
+    scans: u64,
+    scan_duration: Duration,
+    /// Durations of the recent scans, the newest last
+    scan_durations: VecDeque<Duration>,
+}
+
+/// Observation counts per bucket (not cumulative), with their sum
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; TRIGGER_DURATION_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        let bucket = TRIGGER_DURATION_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(TRIGGER_DURATION_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Statistics of the durations of the recent scans
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScanDurations {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    /// Number of scans they are taken over
+    pub count: usize,
+}
+
+impl Metrics {
+    /// A trigger was run for the interface's lease change
+    pub fn triggered(&self, iface_name: &str) {
+        let mut data = self.lock();
+        *data.triggers.entry(iface_name.to_owned()).or_default() += 1;
+        data.last_change.insert(iface_name.to_owned(), unix_time());
+    }
+
+    /// A trigger run of the interface completed: a failure (once the
+    /// retries are exhausted) marks the interface failed, a success clears it
+    pub fn trigger_completed(&self, iface_name: &str, success: bool) {
+        let mut data = self.lock();
+        if !success {
+            *data
+                .trigger_failures
+                .entry(iface_name.to_owned())
+                .or_default() += 1;
+        }
+        data.interface_failed
+            .insert(iface_name.to_owned(), u64::from(!success));
+    }
+
+    /// Interfaces whose last trigger failed
+    pub fn failed_interfaces(&self) -> BTreeSet<String> {
+        self.lock()
+            .interface_failed
+            .iter()
+            .filter(|(_, failed)| **failed != 0)
+            .map(|(iface_name, _)| iface_name.to_owned())
+            .collect()
+    }
+
+    /// A trigger script of the family ran (or timed out) in the duration
+    pub fn trigger_duration(&self, family: &str, duration: Duration) {
+        self.lock()
+            .trigger_durations
+            .entry(family.to_owned())
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    /// The interface's lease failed to be read or parsed
+    pub fn lease_error(&self, iface_name: &str) {
+        *self
+            .lock()
+            .lease_errors
+            .entry(iface_name.to_owned())
+            .or_default() += 1;
+    }
+
+    /// A scan completed, returns the statistics of the recent scans
+    pub fn scanned(&self, duration: Duration) -> ScanDurations {
+        let mut data = self.lock();
+        data.scans += 1;
+        data.scan_duration = duration;
+        if data.scan_durations.len() == SCAN_DURATION_WINDOW {
+            data.scan_durations.pop_front();
+        }
+        data.scan_durations.push_back(duration);
+        data.scan_duration_stats()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MetricsData> {
+        // The counters stay usable even if an updater panicked
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let data = self.lock();
+        let mut out = String::new();
+
+        let per_iface = [
+            (
+                "dhcpleasemon_triggers_total",
+                "counter",
+                "Lease change triggers run",
+                &data.triggers,
+            ),
+            (
+                "dhcpleasemon_trigger_failures_total",
+                "counter",
+                "Trigger runs that failed",
+                &data.trigger_failures,
+            ),
+            (
+                "dhcpleasemon_interface_failed",
+                "gauge",
+                "Whether the last trigger of the interface failed, after its retries",
+                &data.interface_failed,
+            ),
+            (
+                "dhcpleasemon_last_change_timestamp_seconds",
+                "gauge",
+                "Time of the last lease change",
+                &data.last_change,
+            ),
+            (
+                "dhcpleasemon_lease_errors_total",
+                "counter",
+                "Lease files that failed to be read or parsed",
+                &data.lease_errors,
+            ),
+        ];
+        for (name, kind, help, values) in per_iface {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (iface_name, value) in values {
+                let _ = writeln!(
+                    out,
+                    "{}{{iface=\"{}\"}} {}",
+                    name,
+                    escape_label(iface_name),
+                    value
+                );
+            }
+        }
+
+        let name = "dhcpleasemon_trigger_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Duration of the trigger script runs", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (family, histogram) in &data.trigger_durations {
+            let family = escape_label(family);
+            let bounds = TRIGGER_DURATION_BUCKETS
+                .iter()
+                .map(|bound| bound.to_string())
+                .chain([String::from("+Inf")]);
+            let mut cumulative = 0;
+            for (bound, count) in bounds.zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{family=\"{}\",le=\"{}\"}} {}",
+                    name, family, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_sum{{family=\"{}\"}} {}",
+                name, family, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "{}_count{{family=\"{}\"}} {}",
+                name, family, histogram.count
+            );
+        }
+
+        let _ = writeln!(out, "# HELP dhcpleasemon_scans_total Scans completed");
+        let _ = writeln!(out, "# TYPE dhcpleasemon_scans_total counter");
+        let _ = writeln!(out, "dhcpleasemon_scans_total {}", data.scans);
+        let _ = writeln!(
+            out,
+            "# HELP dhcpleasemon_scan_duration_seconds Duration of the last scan"
+        );
+        let _ = writeln!(out, "# TYPE dhcpleasemon_scan_duration_seconds gauge");
+        let _ = writeln!(
+            out,
+            "dhcpleasemon_scan_duration_seconds {}",
+            data.scan_duration.as_secs_f64()
+        );
+
+        let stats = data.scan_duration_stats();
+        for (stat, help, value) in [
+            ("min", "Shortest", stats.min),
+            ("avg", "Average", stats.avg),
+            ("max", "Longest", stats.max),
+        ] {
+            let name = format!("dhcpleasemon_scan_duration_{}_seconds", stat);
+            let _ = writeln!(
+                out,
+                "# HELP {} {} scan duration over the last {} scans",
+                name, help, SCAN_DURATION_WINDOW
+            );
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{} {}", name, value.as_secs_f64());
+        }
+
+        out
+    }
+}
+
+impl MetricsData {
+    fn scan_duration_stats(&self) -> ScanDurations {
+        let count = self.scan_durations.len();
+        if count == 0 {
+            return ScanDurations::default();
+        }
+        let total: Duration = self.scan_durations.iter().sum();
+        ScanDurations {
+            min: self
+                .scan_durations
+                .iter()
+                .min()
+                .copied()
+                .unwrap_or_default(),
+            avg: total / count as u32,
+            max: self
+                .scan_durations
+                .iter()
+                .max()
+                .copied()
+                .unwrap_or_default(),
+            count,
+        }
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Starts serving the metrics on `/metrics` at the address
+pub fn serve(listen: &str, metrics: Arc<Metrics>) -> io::Result<()> {
+    let server = tiny_http::Server::http(listen).map_err(io::Error::other)?;
+
+    thread::Builder::new()
+        .name(String::from("metrics"))
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                debug!("Metrics request: {} {}", request.method(), request.url());
+                let response = if request.url() == "/metrics" {
+                    tiny_http::Response::from_string(metrics.render()).with_header(
+                        tiny_http::Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+                            .expect("Invalid metrics header"),
+                    )
+                } else {
+                    tiny_http::Response::from_string("Not found\n").with_status_code(404)
+                };
+                if let Err(e) = request.respond(response) {
+                    error!("Failed to send metrics: {}", e);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interface_failed_until_a_trigger_succeeds() {
+        let metrics = Metrics::default();
+        metrics.trigger_completed("em0", false);
+        metrics.trigger_completed("em0", false);
+        metrics.trigger_completed("em1", true);
+        assert_eq!(
+            metrics.failed_interfaces(),
+            BTreeSet::from([String::from("em0")])
+        );
+        let out = metrics.render();
+        assert!(out.contains("dhcpleasemon_interface_failed{iface=\"em0\"} 1\n"));
+        assert!(out.contains("dhcpleasemon_interface_failed{iface=\"em1\"} 0\n"));
+        assert!(out.contains("dhcpleasemon_trigger_failures_total{iface=\"em0\"} 2\n"));
+
+        metrics.trigger_completed("em0", true);
+        assert!(metrics.failed_interfaces().is_empty());
+        let out = metrics.render();
+        assert!(out.contains("dhcpleasemon_interface_failed{iface=\"em0\"} 0\n"));
+        assert!(out.contains("dhcpleasemon_trigger_failures_total{iface=\"em0\"} 2\n"));
+    }
+
+    #[test]
+    fn trigger_durations_are_cumulative_buckets_per_family() {
+        let metrics = Metrics::default();
+        for millis in [50, 100, 400, 2_000, 10_000, 60_000] {
+            metrics.trigger_duration("inet", Duration::from_millis(millis));
+        }
+        metrics.trigger_duration("inet6", Duration::from_secs(45));
+
+        let out = metrics.render();
+        let name = "dhcpleasemon_trigger_duration_seconds";
+        for line in [
+            format!("# TYPE {} histogram", name),
+            format!("{}_bucket{{family=\"inet\",le=\"0.1\"}} 2", name),
+            format!("{}_bucket{{family=\"inet\",le=\"1\"}} 3", name),
+            format!("{}_bucket{{family=\"inet\",le=\"5\"}} 4", name),
+            format!("{}_bucket{{family=\"inet\",le=\"30\"}} 5", name),
+            format!("{}_bucket{{family=\"inet\",le=\"+Inf\"}} 6", name),
+            format!("{}_sum{{family=\"inet\"}} 72.55", name),
+            format!("{}_count{{family=\"inet\"}} 6", name),
+            format!("{}_bucket{{family=\"inet6\",le=\"30\"}} 0", name),
+            format!("{}_bucket{{family=\"inet6\",le=\"+Inf\"}} 1", name),
+            format!("{}_count{{family=\"inet6\"}} 1", name),
+        ] {
+            assert!(
+                out.lines().any(|l| l == line),
+                "{} missing in:\n{}",
+                line,
+                out
+            );
+        }
+    }
+}
+
+// EOF
@@ -0,0 +1,2767 @@
+//! The monitor: scans the lease files of the interfaces, and runs the
+//! trigger scripts (and notifies) on lease changes
+
+use crate::args::Args;
+use crate::config::Config;
+use crate::control::SharedStatus;
+use crate::history::{HistoryEntry, SharedHistory, TriggerStatus};
+use crate::lease::{self, Lease6, Lease6Params, LeaseEvent, LeaseParams, LeaseSource};
+use crate::metrics::Metrics;
+use crate::mqtt::Mqtt;
+use crate::route::{self, RouteLookup, RouteProvider, RouteTable};
+use crate::trigger::{self, ScriptEnv, TriggerJob, TriggerWorkers};
+use crate::webhook::Webhook;
+use crate::{state, status, systemd, unix_time};
+use clap::Parser;
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Last seen state of a lease file, a file is modified if any of it differs
+#[derive(PartialEq, Debug)]
+struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+    inode: u64,
+}
+
+impl FileStamp {
+    fn new(metadata: &fs::Metadata) -> io::Result<Self> {
+        Ok(Self {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+            inode: metadata.ino(),
+        })
+    }
+}
+
+/// How lease file changes are detected
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DetectBy {
+    /// Modification time (falls back to hash while the clock is unsynchronized)
+    #[default]
+    Mtime,
+    /// Hash of the file content
+    Hash,
+}
+
+/// Errors that abort checking an interface in the current scan
+#[derive(Debug)]
+pub enum MonitorError {
+    /// Lease file could not be accessed
+    LeaseFile { path: String, source: io::Error },
+    /// Lease file still lacks required fields after retrying
+    IncompleteLeaseFile { path: String },
+    /// Lease file holds an invalid address, it is not triggered
+    InvalidLease { path: String, reason: String },
+    /// Lease could not be obtained from the DHCP client
+    LeaseDump {
+        command: &'static str,
+        source: io::Error,
+    },
+    /// Routing table could not be obtained
+    RouteLookup {
+        method: &'static str,
+        source: io::Error,
+    },
+    /// Trigger script could not be executed
+    Script { path: String, source: io::Error },
+    /// Trigger script ran longer than the script timeout and was killed
+    ScriptTimeout { path: String, timeout: u64 },
+}
+
+impl fmt::Display for MonitorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MonitorError::LeaseFile { path, source } => {
+                write!(f, "Failed to access lease file {}: {}", path, source)
+            }
+            MonitorError::IncompleteLeaseFile { path } => {
+                write!(f, "Incomplete lease file {}", path)
+            }
+            MonitorError::InvalidLease { path, reason } => {
+                write!(f, "Invalid lease file {}: {}", path, reason)
+            }
+            MonitorError::LeaseDump { command, source } => {
+                write!(f, "Failed to get the lease from {}: {}", command, source)
+            }
+            MonitorError::RouteLookup { method, source } => {
+                write!(f, "Failed to look up routes ({}): {}", method, source)
+            }
+            MonitorError::Script { path, source } => {
+                write!(f, "Failed to execute script {}: {}", path, source)
+            }
+            MonitorError::ScriptTimeout { path, timeout } => {
+                write!(f, "Script {} timed out after {}s, killed", path, timeout)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MonitorError {}
+
+impl MonitorError {
+    /// Is it a lease file that does not exist (e.g. removed while checked)?
+    fn is_missing_lease_file(&self) -> bool {
+        matches!(self, MonitorError::LeaseFile { source, .. } if source.kind() == io::ErrorKind::NotFound)
+    }
+}
+
+/// How many times a lease file that looks incomplete is read again
+const LEASE_READ_RETRIES: u32 = 3;
+
+/// Delay before reading a lease file that looks incomplete again
+const LEASE_READ_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Any earlier system time means the clock has not been synchronized yet
+/// (2024-01-01T00:00:00Z)
+const CLOCK_SANE_MIN_SECS: u64 = 1_704_067_200;
+
+/// How often a sleeping main loop checks for a shutdown request
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lease changes of an interface found in a scan, to be triggered
+struct InterfaceChange {
+    iface_name: String,
+    lease_params: Option<(LeaseEvent, LeaseParams)>,
+    lease6_params: Option<(LeaseEvent, Lease6Params)>,
+}
+
+impl InterfaceChange {
+    /// Address families that changed: inet, inet6
+    fn families(&self) -> Vec<&'static str> {
+        let mut families = Vec::new();
+        if self.lease_params.is_some() {
+            families.push("inet");
+        }
+        if self.lease6_params.is_some() {
+            families.push("inet6");
+        }
+        families
+    }
+
+    /// Merges a later change of the interface into this one
+    fn merge(&mut self, later: InterfaceChange) {
+        if later.lease_params.is_some() {
+            self.lease_params = later.lease_params;
+        }
+        if later.lease6_params.is_some() {
+            self.lease6_params = later.lease6_params;
+        }
+    }
+}
+
+/// What a scan did
+#[derive(Debug, Default)]
+struct ScanSummary {
+    /// Triggers run (queued)
+    triggered: usize,
+    /// Interfaces that failed to be checked, and a failed pre-trigger hook
+    errors: usize,
+}
+
+pub struct Monitor {
+    config: Config,
+    timestamps: HashMap<String, FileStamp>,
+    /// Time of the last change of the lease files waiting to settle
+    /// (`--debounce`)
+    pending_changes: HashMap<String, Instant>,
+    hashes: HashMap<String, u64>,
+    clock_synced: bool,
+    lease_params: HashMap<String, LeaseParams>,
+    lease6_params: HashMap<String, Lease6Params>,
+    route_provider: Box<dyn RouteProvider>,
+    lease_source: Box<dyn LeaseSource>,
+    route_tables: HashMap<String, RouteTable>,
+    triggers: TriggerWorkers,
+    metrics: Arc<Metrics>,
+    /// Sends the lease changes to `--webhook-url`, started on first use
+    webhook: Option<Webhook>,
+    /// Publishes the lease changes to `--mqtt-broker`, connected on first use
+    mqtt: Option<Mqtt>,
+    /// Trigger scripts found missing (warned about once)
+    missing_scripts: HashSet<String>,
+    /// Interfaces found by auto-discovery in the last scan
+    discovered_interfaces: BTreeSet<String>,
+    /// Interfaces checked in the scans: the configured ones, plus the
+    /// discovered ones (updated on every scan)
+    monitored_interfaces: Vec<String>,
+    /// Recorded leases of the interfaces dropped with their leases kept,
+    /// restored when monitored again (`--no-trigger-on-rediscovery`)
+    parked_lease_params: HashMap<String, LeaseParams>,
+    parked_lease6_params: HashMap<String, Lease6Params>,
+    /// Interfaces whose last trigger failed, as in the status
+    failed_interfaces: BTreeSet<String>,
+    /// Still in the first scan
+    startup: bool,
+    /// Status answered on the control socket
+    status: SharedStatus,
+    /// Time of the last lease change per interface, for the status file
+    last_change: HashMap<String, u64>,
+    /// Recent lease changes answered on the control socket
+    history: SharedHistory,
+    /// State of the random number generator of `--jitter`
+    rng: u64,
+    /// Time of the last trigger per interface (`--cooldown`)
+    last_triggered: HashMap<String, Instant>,
+    /// Changes held back until the interface's cooldown passed
+    deferred_changes: BTreeMap<String, InterfaceChange>,
+    /// Changes held back by a failed pre-trigger hook, until the next scan
+    postponed_changes: BTreeMap<String, InterfaceChange>,
+    /// Expiry of the current lease per interface, if the lease records it
+    lease_expiry: HashMap<String, u64>,
+    /// Expiry already warned about per interface (`--expiry-warning`)
+    expiry_warned: HashMap<String, u64>,
+    /// Lease params changed since the state file was saved
+    state_changed: bool,
+    shutdown: Arc<AtomicBool>,
+    reload: Arc<AtomicBool>,
+    /// Set by SIGUSR1: the next scan re-runs the triggers of every lease
+    retrigger: Arc<AtomicBool>,
+    /// Set by SIGUSR2: the internal state is logged between scans
+    dump: Arc<AtomicBool>,
+}
+
+impl Monitor {
+    pub fn new(config: Config) -> Self {
+        let route_provider = route::provider(config.route_lookup, &config.netstat_path);
+        Self::with_route_provider(config, route_provider)
+    }
+
+    /// A monitor getting its routes from the given provider instead of the
+    /// configured route lookup, e.g. a mock one returning canned routes
+    pub fn with_route_provider(mut config: Config, route_provider: Box<dyn RouteProvider>) -> Self {
+        config.check_interfaces();
+        let metrics = Arc::new(Metrics::default());
+        Self {
+            route_provider,
+            lease_source: lease::source(config.lease_format, &config.dhcpcd_path),
+            monitored_interfaces: config.interface_names(),
+            config,
+            timestamps: HashMap::new(),
+            pending_changes: HashMap::new(),
+            hashes: HashMap::new(),
+            clock_synced: true,
+            lease_params: HashMap::new(),
+            lease6_params: HashMap::new(),
+            route_tables: HashMap::new(),
+            triggers: TriggerWorkers::new(Arc::clone(&metrics)),
+            metrics,
+            webhook: None,
+            mqtt: None,
+            missing_scripts: HashSet::new(),
+            discovered_interfaces: BTreeSet::new(),
+            parked_lease_params: HashMap::new(),
+            parked_lease6_params: HashMap::new(),
+            failed_interfaces: BTreeSet::new(),
+            startup: true,
+            status: SharedStatus::default(),
+            last_change: HashMap::new(),
+            history: SharedHistory::default(),
+            rng: rng_seed(),
+            last_triggered: HashMap::new(),
+            deferred_changes: BTreeMap::new(),
+            postponed_changes: BTreeMap::new(),
+            lease_expiry: HashMap::new(),
+            expiry_warned: HashMap::new(),
+            state_changed: false,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            reload: Arc::new(AtomicBool::new(false)),
+            retrigger: Arc::new(AtomicBool::new(false)),
+            dump: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Was the file modified since the last check?
+    fn check_file_modified(&mut self, lease_file_path: &str) -> Result<bool, MonitorError> {
+        if self.config.detect_by == DetectBy::Hash || !self.check_clock_synced() {
+            return self.check_file_content_changed(lease_file_path);
+        }
+
+        let current_timestamp = fs::metadata(lease_file_path)
+            .and_then(|metadata| FileStamp::new(&metadata))
+            .map_err(|source| MonitorError::LeaseFile {
+                path: lease_file_path.to_string(),
+                source,
+            })?;
+
+        // Any difference counts, the clock may have been stepped backwards
+        if self.timestamps.get(lease_file_path) != Some(&current_timestamp) {
+            // Store the new timestamp
+            self.timestamps
+                .insert(lease_file_path.to_string(), current_timestamp);
+
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Did the file content change since the last check?
+    fn check_file_content_changed(&mut self, lease_file_path: &str) -> Result<bool, MonitorError> {
+        let content = fs::read(lease_file_path).map_err(|source| MonitorError::LeaseFile {
+            path: lease_file_path.to_string(),
+            source,
+        })?;
+
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let current_hash = hasher.finish();
+
+        if self.hashes.get(lease_file_path) != Some(&current_hash) {
+            self.hashes
+                .insert(lease_file_path.to_string(), current_hash);
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Forgets the last seen state of the file so it's checked again on the next scan
+    fn forget_file(&mut self, lease_file_path: &str) {
+        self.timestamps.remove(lease_file_path);
+        self.hashes.remove(lease_file_path);
+        self.pending_changes.remove(lease_file_path);
+    }
+
+    /// Is a change of the file due (with `--debounce`: has the file settled,
+    /// i.e. not been modified again within the debounce window)? The changes
+    /// found at startup are due right away.
+    fn check_change_due(&mut self, lease_file_path: &str, modified: bool) -> bool {
+        if self.config.debounce.is_zero() || self.startup {
+            return modified;
+        }
+
+        let now = Instant::now();
+        if modified {
+            if self.pending_changes.contains_key(lease_file_path) {
+                debug!("Lease file {} changed again, debouncing", lease_file_path);
+            }
+            self.pending_changes
+                .insert(lease_file_path.to_string(), now);
+        }
+
+        match self.pending_changes.get(lease_file_path) {
+            Some(changed) if now.duration_since(*changed) >= self.config.debounce => {
+                self.pending_changes.remove(lease_file_path);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Does the system clock look synchronized? Logs when this changes.
+    fn check_clock_synced(&mut self) -> bool {
+        let clock_synced = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs() >= CLOCK_SANE_MIN_SECS)
+            .unwrap_or(false);
+
+        if clock_synced != self.clock_synced {
+            if clock_synced {
+                info!("Clock looks synchronized, detecting changes by modification time");
+            } else {
+                warn!("Clock looks unsynchronized, detecting changes by content hash");
+            }
+            self.clock_synced = clock_synced;
+        }
+
+        clock_synced
+    }
+
+    /// Generates the lease file path for a given interface
+    fn get_lease_file_path(&self, iface_name: &str) -> String {
+        self.find_lease_file(iface_name, "inet", &self.config.lease_file_template)
+    }
+
+    /// Generates the lease (IPv6) file path for a given interface
+    fn get_lease6_file_path(&self, iface_name: &str) -> String {
+        self.find_lease_file(iface_name, "inet6", &self.config.lease6_file_template)
+    }
+
+    /// Directories the interface's lease files of proto are looked for in:
+    /// its own (or the global) lease directory, then the extra ones
+    fn get_lease_dirs(&self, iface_name: &str, proto: &str) -> Vec<&str> {
+        let iface = self.config.interface(iface_name);
+        let lease_dir = match proto {
+            "inet6" => iface
+                .and_then(|iface| iface.dhcp6_lease_dir.as_ref())
+                .unwrap_or(&self.config.dhcp6_lease_dir),
+            _ => iface
+                .and_then(|iface| iface.dhcp_lease_dir.as_ref())
+                .unwrap_or(&self.config.dhcp_lease_dir),
+        };
+
+        let mut lease_dirs = vec![lease_dir.as_str()];
+        lease_dirs.extend(
+            self.config
+                .extra_lease_dir
+                .iter()
+                .filter(|extra| extra.proto == proto)
+                .map(|extra| extra.dir.as_str()),
+        );
+        lease_dirs
+    }
+
+    /// Path of the lease file in the first lease directory that has it, or
+    /// in the first one if none does
+    fn find_lease_file(&self, iface_name: &str, proto: &str, lease_file_template: &str) -> String {
+        let lease_file_paths: Vec<String> = self
+            .get_lease_dirs(iface_name, proto)
+            .into_iter()
+            .map(|lease_dir| lease::lease_file_path(lease_dir, lease_file_template, iface_name))
+            .collect();
+        lease_file_paths
+            .iter()
+            .find(|lease_file_path| Path::new(lease_file_path).exists())
+            .unwrap_or(&lease_file_paths[0])
+            .to_owned()
+    }
+
+    /// Generates the trigger script path for a given interface
+    fn get_trigger_script_path(&self, iface_name: &str) -> String {
+        let trigger_scripts_path = &self.config.scripts_dir;
+        let iface = self.config.interface(iface_name);
+        if let Some(trigger_script) = iface.and_then(|iface| iface.trigger_script.as_ref()) {
+            return Path::new(trigger_scripts_path)
+                .join(trigger_script)
+                .to_string_lossy()
+                .into_owned();
+        }
+        let trigger_scripts_prefix = iface
+            .and_then(|iface| iface.trigger_script_prefix.as_ref())
+            .unwrap_or(&self.config.trigger_script_prefix);
+        self.or_shared_script(trigger::trigger_script_path(
+            trigger_scripts_path,
+            trigger_scripts_prefix,
+            iface_name,
+        ))
+    }
+
+    /// Generates the (IPv6) trigger script path for a given interface
+    fn get_trigger_script_path_ipv6(&self, iface_name: &str) -> String {
+        let trigger_scripts_path = &self.config.scripts_dir;
+        let iface = self.config.interface(iface_name);
+        if let Some(trigger_script) = iface.and_then(|iface| iface.trigger_script_ipv6.as_ref()) {
+            return Path::new(trigger_scripts_path)
+                .join(trigger_script)
+                .to_string_lossy()
+                .into_owned();
+        }
+        let trigger_scripts_prefix = iface
+            .and_then(|iface| iface.trigger_script_prefix_ipv6.as_ref())
+            .unwrap_or(&self.config.trigger_script_prefix_ipv6);
+        self.or_shared_script(trigger::trigger_script_path(
+            trigger_scripts_path,
+            trigger_scripts_prefix,
+            iface_name,
+        ))
+    }
+
+    /// The shared trigger script (`--shared-script`, if any) unless the
+    /// interface's own script (or its `.d` directory) exists
+    fn or_shared_script(&self, script_path: String) -> String {
+        match &self.config.shared_script {
+            Some(shared_script)
+                if !Path::new(&script_path).exists()
+                    && !Path::new(&format!("{}.d", script_path)).exists() =>
+            {
+                shared_script.to_owned()
+            }
+            _ => script_path,
+        }
+    }
+
+    /// Gets the routing table for proto, once per scan
+    fn get_route_table(&mut self, proto: &str) -> Result<&RouteTable, MonitorError> {
+        if !self.route_tables.contains_key(proto) {
+            let route_table = self.route_provider.route_table(proto)?;
+            self.route_tables.insert(proto.to_string(), route_table);
+        }
+
+        Ok(&self.route_tables[proto])
+    }
+
+    /// Gets the default route for iface, along with the matching strategy
+    /// that found it ("iface" or "global")
+    fn get_default_route(
+        &mut self,
+        iface_name: &str,
+        proto: &str,
+    ) -> Result<Option<(String, String)>, MonitorError> {
+        let route_fallback_global = self.config.route_fallback_global;
+        let route_table = self.get_route_table(proto)?;
+
+        if let Some(route_ip) = route_table.default_routes.get(iface_name) {
+            return Ok(Some((route_ip.to_owned(), String::from("iface"))));
+        }
+
+        if route_fallback_global {
+            if let Some(route_ip) = &route_table.global_default_route {
+                return Ok(Some((route_ip.to_owned(), String::from("global"))));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the whole lease file
+    fn read_lease_file(&self, lease_file_path: &str) -> Result<String, MonitorError> {
+        // Not necessarily text (dhcpcd)
+        fs::read(lease_file_path)
+            .map(|content| String::from_utf8_lossy(&content).into_owned())
+            .map_err(|source| MonitorError::LeaseFile {
+                path: lease_file_path.to_string(),
+                source,
+            })
+    }
+
+    /// Reads a lease file that may be in the middle of being rewritten:
+    /// reads it again (a few times) while it changes during the read or looks
+    /// incomplete (`IncompleteLeaseFile`)
+    fn read_complete_lease_file<T>(
+        &self,
+        lease_file_path: &str,
+        parse: impl Fn(&str) -> Result<T, MonitorError>,
+    ) -> Result<T, MonitorError> {
+        let stamp = || {
+            fs::metadata(lease_file_path)
+                .and_then(|metadata| FileStamp::new(&metadata))
+                .map_err(|source| MonitorError::LeaseFile {
+                    path: lease_file_path.to_string(),
+                    source,
+                })
+        };
+
+        for attempt in 0..=LEASE_READ_RETRIES {
+            if attempt > 0 {
+                sleep(LEASE_READ_RETRY_DELAY);
+            }
+
+            let stamp_before = stamp()?;
+            let content = self.read_lease_file(lease_file_path)?;
+            if stamp()? == stamp_before {
+                match parse(&content) {
+                    Err(MonitorError::IncompleteLeaseFile { .. }) => {}
+                    result => return result,
+                }
+            }
+            debug!(
+                "Lease file {} is being rewritten, retrying",
+                lease_file_path
+            );
+        }
+
+        Err(MonitorError::IncompleteLeaseFile {
+            path: lease_file_path.to_string(),
+        })
+    }
+
+    /// Absolute path and modification time (seconds since the epoch, empty
+    /// if it is gone) of the lease file that caused a trigger
+    fn get_lease_file_info(&self, lease_file_path: &str) -> (String, String) {
+        let path = std::path::absolute(lease_file_path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| lease_file_path.to_owned());
+        // Seen by the last check, unless detecting changes by content
+        let modified = match self.timestamps.get(lease_file_path) {
+            Some(stamp) => Some(stamp.modified),
+            None => fs::metadata(lease_file_path)
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+        };
+        let mtime = modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|mtime| mtime.as_secs().to_string())
+            .unwrap_or_default();
+        (path, mtime)
+    }
+
+    /// Environment of the IPv4 trigger scripts, also published over MQTT
+    fn get_trigger_env(
+        &self,
+        lease_params: &LeaseParams,
+        old_lease_params: Option<&LeaseParams>,
+        event: LeaseEvent,
+        changed_families: &str,
+    ) -> ScriptEnv {
+        let default_route = lease_params.route_addr.to_owned();
+        let default_route_match = lease_params.route_match.to_owned();
+        let lease_ip_addr = lease::or_empty(lease_params.ip_addr);
+        let (old_ip_addr, old_route_addr) = match old_lease_params {
+            Some(old) => (lease::or_empty(old.ip_addr), old.route_addr.to_owned()),
+            None => (String::from(""), String::from("")),
+        };
+        let (lease_file, lease_mtime) =
+            self.get_lease_file_info(&self.get_lease_file_path(&lease_params.iface_name));
+
+        let mut env = ScriptEnv::default();
+        env.env("DHCP_IFACE", &lease_params.iface_name)
+            .env("DHCP_IP_ADDR", lease_ip_addr)
+            .env("DHCP_IP_ROUTE", default_route)
+            .env("DHCP_IP_ROUTE_MATCH", default_route_match)
+            .env("DHCP_OLD_IP_ADDR", old_ip_addr)
+            .env("DHCP_OLD_IP_ROUTE", old_route_addr)
+            .env("DHCP_NEXT_SERVER", &lease_params.next_server)
+            .env("DHCP_FILENAME", &lease_params.filename)
+            .env("DHCP_HOST_NAME", &lease_params.host_name)
+            .env("DHCP_DOMAIN", &lease_params.domain_name)
+            .env(
+                "DHCP_DNS",
+                lease::join_addrs(&lease_params.dns_servers, &self.config.list_separator),
+            )
+            .env("DHCP_LEASE_TIME", lease::or_empty(lease_params.lease_time))
+            .env("DHCP_LEASE_FILE", lease_file)
+            .env("DHCP_LEASE_MTIME", lease_mtime)
+            .env("DHCP_FAMILY", "inet")
+            .env("DHCP_EVENT", event.to_string())
+            .env("DHCP_CHANGED_FAMILIES", changed_families);
+        env.rename_prefix(&self.config.env_prefix);
+
+        if self.config.dhclient_compat {
+            env.envs(Self::get_dhclient_env(
+                lease_params,
+                old_lease_params,
+                event,
+            ));
+        }
+
+        env
+    }
+
+    /// Environment of the IPv6 trigger scripts, also published over MQTT
+    fn get_trigger6_env(
+        &self,
+        lease_params: &Lease6Params,
+        old_lease_params: Option<&Lease6Params>,
+        event: LeaseEvent,
+        changed_families: &str,
+    ) -> ScriptEnv {
+        let default_route = lease_params.route6_addr.to_owned();
+        let default_route_match = lease_params.route6_match.to_owned();
+        let lease_ip_prefix = lease::or_empty(lease_params.ip6_prefix);
+        let lease_ip_prefix_len = lease::or_empty(lease_params.ip6_prefix_len);
+        let (old_ip_prefix, old_ip_prefix_len, old_route_addr) = match old_lease_params {
+            Some(old) => (
+                lease::or_empty(old.ip6_prefix),
+                lease::or_empty(old.ip6_prefix_len),
+                old.route6_addr.to_owned(),
+            ),
+            None => (String::from(""), String::from(""), String::from("")),
+        };
+        let (lease_file, lease_mtime) =
+            self.get_lease_file_info(&self.get_lease6_file_path(&lease_params.iface_name));
+
+        let mut env = ScriptEnv::default();
+        env.env("DHCP6_IFACE", &lease_params.iface_name)
+            .env("DHCP6_IP_PREFIX", lease_ip_prefix)
+            .env("DHCP6_IP_PREFIX_LEN", lease_ip_prefix_len)
+            .env("DHCP6_IP_ADDR", lease::or_empty(lease_params.ip6_addr))
+            .env("DHCP6_IP_ROUTE", default_route)
+            .env("DHCP6_IP_ROUTE_MATCH", default_route_match)
+            .env(
+                "DHCP6_DNS",
+                lease::join_addrs(&lease_params.dns_servers, &self.config.list_separator),
+            )
+            .env("DHCP6_LEASE_FILE", lease_file)
+            .env("DHCP6_LEASE_MTIME", lease_mtime)
+            .env("DHCP6_OLD_IP_PREFIX", old_ip_prefix)
+            .env("DHCP6_OLD_IP_PREFIX_LEN", old_ip_prefix_len)
+            .env("DHCP6_OLD_IP_ROUTE", old_route_addr)
+            .env(
+                "DHCP6_IP_PREFIX_COUNT",
+                lease_params.ip6_prefixes.len().to_string(),
+            )
+            .env(
+                "DHCP6_IP_PREFIXES",
+                lease::join_addrs(
+                    &lease_params.ip6_prefix_cidrs(),
+                    &self.config.list_separator,
+                ),
+            )
+            .env("DHCP_FAMILY", "inet6")
+            .env("DHCP_EVENT", event.to_string())
+            .env("DHCP_CHANGED_FAMILIES", changed_families);
+
+        for (i, (ip_prefix, ip_prefix_len)) in lease_params.ip6_prefixes.iter().enumerate() {
+            env.env(format!("DHCP6_IP_PREFIX_{}", i), ip_prefix.to_string())
+                .env(
+                    format!("DHCP6_IP_PREFIX_LEN_{}", i),
+                    ip_prefix_len.to_string(),
+                );
+        }
+        env.rename_prefix(&self.config.env_prefix);
+
+        if self.config.dhclient_compat {
+            env.envs(Self::get_dhclient6_env(
+                lease_params,
+                old_lease_params,
+                event,
+            ));
+        }
+
+        env
+    }
+
+    fn run_trigger_script(
+        &mut self,
+        lease_params: &LeaseParams,
+        old_lease_params: Option<&LeaseParams>,
+        event: LeaseEvent,
+        changed_families: &str,
+        history_id: Option<u64>,
+    ) {
+        let iface_name = lease_params.iface_name.to_owned();
+        let trigger_script_path = self.get_trigger_script_path(&iface_name);
+
+        let trigger_script_paths = self.get_trigger_scripts(&iface_name, &trigger_script_path);
+        if trigger_script_paths.is_empty() {
+            return;
+        }
+
+        if self.config.shadow {
+            info!(
+                "Shadow mode, not running trigger script: {} (changed: {})",
+                &trigger_script_path, changed_families,
+            );
+            return;
+        }
+
+        let env = self.get_trigger_env(lease_params, old_lease_params, event, changed_families);
+        let args = Self::get_trigger_args(lease_params);
+
+        let stdin = self.config.json_stdin.then(|| {
+            Self::get_lease_json(
+                &lease_params.iface_name,
+                "inet",
+                lease_params,
+                old_lease_params,
+                event,
+                changed_families,
+            )
+        });
+
+        self.queue_trigger_scripts(
+            (&lease_params.iface_name, "inet"),
+            trigger_script_paths,
+            env,
+            args,
+            stdin,
+            history_id.into_iter().collect(),
+        );
+    }
+
+    fn run_trigger_script_ipv6(
+        &mut self,
+        lease_params: &Lease6Params,
+        old_lease_params: Option<&Lease6Params>,
+        event: LeaseEvent,
+        changed_families: &str,
+        history_id: Option<u64>,
+    ) {
+        let iface_name = lease_params.iface_name.to_owned();
+        let trigger_script_path = self.get_trigger_script_path_ipv6(&iface_name);
+
+        let trigger_script_paths = self.get_trigger_scripts(&iface_name, &trigger_script_path);
+        if trigger_script_paths.is_empty() {
+            return;
+        }
+
+        if self.config.shadow {
+            info!(
+                "Shadow mode, not running trigger script: {} (changed: {})",
+                &trigger_script_path, changed_families,
+            );
+            return;
+        }
+
+        let env = self.get_trigger6_env(lease_params, old_lease_params, event, changed_families);
+        let args = Self::get_trigger6_args(lease_params);
+
+        let stdin = self.config.json_stdin.then(|| {
+            Self::get_lease_json(
+                &lease_params.iface_name,
+                "inet6",
+                lease_params,
+                old_lease_params,
+                event,
+                changed_families,
+            )
+        });
+
+        self.queue_trigger_scripts(
+            (&lease_params.iface_name, "inet6"),
+            trigger_script_paths,
+            env,
+            args,
+            stdin,
+            history_id.into_iter().collect(),
+        );
+    }
+
+    /// Runs the IPv4 trigger script once for both leases of the interface
+    /// (`--unified-trigger`), with the IPv4 and the IPv6 environment; the
+    /// leases are given with the previous ones and the events
+    fn run_trigger_script_dual(
+        &mut self,
+        (lease_params, old_lease_params, event): (&LeaseParams, Option<&LeaseParams>, LeaseEvent),
+        (lease6_params, old_lease6_params, event6): (
+            &Lease6Params,
+            Option<&Lease6Params>,
+            LeaseEvent,
+        ),
+        changed_families: &str,
+        history_ids: Vec<u64>,
+    ) {
+        let iface_name = lease_params.iface_name.to_owned();
+        let trigger_script_path = self.get_trigger_script_path(&iface_name);
+
+        let trigger_script_paths = self.get_trigger_scripts(&iface_name, &trigger_script_path);
+        if trigger_script_paths.is_empty() {
+            return;
+        }
+
+        if self.config.shadow {
+            info!(
+                "Shadow mode, not running trigger script: {} (changed: {})",
+                &trigger_script_path, changed_families,
+            );
+            return;
+        }
+
+        // The IPv4 values of the variables both have (DHCP_EVENT, ...) win
+        let prefix = &self.config.env_prefix;
+        let mut env = self.get_trigger_env(lease_params, old_lease_params, event, changed_families);
+        env.env(trigger::env_name(prefix, "DHCP_STACK"), "dual")
+            .env(trigger::env_name(prefix, "DHCP6_EVENT"), event6.to_string());
+        env.merge(self.get_trigger6_env(
+            lease6_params,
+            old_lease6_params,
+            event6,
+            changed_families,
+        ));
+        let mut args = Self::get_trigger_args(lease_params);
+        args.extend(Self::get_trigger6_args(lease6_params).into_iter().skip(1));
+
+        let stdin = self.config.json_stdin.then(|| {
+            serde_json::json!({
+                "iface": lease_params.iface_name,
+                "timestamp": unix_time(),
+                "stack": "dual",
+                "changed_families": changed_families.split_whitespace().collect::<Vec<_>>(),
+                "inet": {
+                    "event": event.to_string(),
+                    "lease": lease_params,
+                    "old_lease": old_lease_params,
+                },
+                "inet6": {
+                    "event": event6.to_string(),
+                    "lease": lease6_params,
+                    "old_lease": old_lease6_params,
+                },
+            })
+            .to_string()
+        });
+
+        self.queue_trigger_scripts(
+            (&lease_params.iface_name, "dual"),
+            trigger_script_paths,
+            env,
+            args,
+            stdin,
+            history_ids,
+        );
+    }
+
+    /// Positional arguments of the IPv4 trigger scripts (`--args-mode`); the
+    /// order is documented, keep it stable
+    fn get_trigger_args(lease_params: &LeaseParams) -> Vec<String> {
+        vec![
+            lease_params.iface_name.to_owned(),
+            lease::or_empty(lease_params.ip_addr),
+            lease_params.route_addr.to_owned(),
+        ]
+    }
+
+    /// Positional arguments of the IPv6 trigger scripts, as above
+    fn get_trigger6_args(lease_params: &Lease6Params) -> Vec<String> {
+        vec![
+            lease_params.iface_name.to_owned(),
+            lease::or_empty(lease_params.ip6_prefix),
+            lease::or_empty(lease_params.ip6_prefix_len),
+            lease_params.route6_addr.to_owned(),
+        ]
+    }
+
+    /// The trigger scripts to run: the script itself and the executable
+    /// entries of its `.d` directory, in lexical order
+    fn get_trigger_scripts(&mut self, iface_name: &str, script_path: &str) -> Vec<String> {
+        let mut script_paths = Vec::new();
+        let script_dir = format!("{}.d", script_path);
+        let has_script_dir = Path::new(&script_dir).is_dir();
+
+        // The single script is only missing if there is no directory either
+        if (!has_script_dir || Path::new(script_path).exists())
+            && self.check_script(iface_name, script_path)
+        {
+            script_paths.push(script_path.to_owned());
+        }
+
+        if has_script_dir {
+            let mut entries: Vec<String> = match fs::read_dir(&script_dir) {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+                    .filter(|entry| {
+                        fs::metadata(entry.path())
+                            .map(|metadata| {
+                                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+                            })
+                            .unwrap_or(false)
+                    })
+                    .map(|entry| entry.path().to_string_lossy().into_owned())
+                    .collect(),
+                Err(e) => {
+                    error!(
+                        "Failed to read trigger script directory {}: {}",
+                        script_dir, e
+                    );
+                    Vec::new()
+                }
+            };
+            entries.sort();
+            script_paths.extend(entries);
+        }
+
+        script_paths
+    }
+
+    /// Does the trigger script exist and can it be run? Warns once about a
+    /// missing script, logs an error about one that is not executable.
+    fn check_script(&mut self, iface_name: &str, script_path: &str) -> bool {
+        let metadata = match fs::metadata(script_path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                if self.missing_scripts.insert(script_path.to_owned()) {
+                    warn!(
+                        "No trigger script for {}: {} does not exist",
+                        iface_name, script_path
+                    );
+                }
+                return false;
+            }
+        };
+        self.missing_scripts.remove(script_path);
+
+        let mode = metadata.permissions().mode();
+        if mode & 0o111 == 0 {
+            error!(
+                "Trigger script for {} is not executable: {} (mode {:o}, chmod +x it)",
+                iface_name,
+                script_path,
+                mode & 0o7777,
+            );
+            return false;
+        }
+
+        let executable = CString::new(script_path)
+            .map(|path| unsafe { libc::access(path.as_ptr(), libc::X_OK) } == 0)
+            .unwrap_or(false);
+        if !executable {
+            error!(
+                "Trigger script for {} is not executable by uid {}: {} (owner uid {}, gid {}, mode {:o})",
+                iface_name,
+                unsafe { libc::getuid() },
+                script_path,
+                metadata.uid(),
+                metadata.gid(),
+                mode & 0o7777,
+            );
+            return false;
+        }
+
+        true
+    }
+
+    /// Checks that every configured interface has its trigger scripts,
+    /// returns whether they all exist and are executable
+    pub fn check_scripts(&mut self) -> bool {
+        let mut all_exist = true;
+        for iface_name in self.config.interface_names() {
+            let trigger_script_path = self.get_trigger_script_path(&iface_name);
+            all_exist &= !self
+                .get_trigger_scripts(&iface_name, &trigger_script_path)
+                .is_empty();
+            if self.config.ipv6 {
+                let trigger_script_path = self.get_trigger_script_path_ipv6(&iface_name);
+                all_exist &= !self
+                    .get_trigger_scripts(&iface_name, &trigger_script_path)
+                    .is_empty();
+            }
+        }
+        all_exist
+    }
+
+    /// Warns about the lease directories that do not exist or cannot be
+    /// read (their lease files are then seen as missing); returns whether
+    /// all are readable
+    pub fn check_lease_dirs(&self) -> bool {
+        let mut all_readable = true;
+        for lease_dir in self.config.lease_dirs() {
+            if let Err(e) = fs::read_dir(lease_dir) {
+                warn!("Lease directory {} is not readable: {}", lease_dir, e);
+                all_readable = false;
+            }
+        }
+        all_readable
+    }
+
+    /// Queues the trigger scripts of the family (`inet`, `inet6` or `dual`)
+    /// on the interface's worker, or with
+    /// `--dry-run` only logs the scripts and their environment
+    fn queue_trigger_scripts(
+        &mut self,
+        (iface_name, family): (&str, &'static str),
+        script_paths: Vec<String>,
+        mut env: ScriptEnv,
+        mut args: Vec<String>,
+        stdin: Option<String>,
+        history_ids: Vec<u64>,
+    ) {
+        if !self.config.args_mode.env() {
+            env = ScriptEnv::default();
+        } else if let Some(iface) = self.config.interface(iface_name) {
+            // The interface's own variables, never in place of the daemon's
+            let mut iface_env = ScriptEnv::default();
+            iface_env.envs(
+                iface
+                    .env
+                    .iter()
+                    .filter(|(name, _)| !self.config.is_reserved_env(name)),
+            );
+            env.merge(iface_env);
+        }
+        if !self.config.args_mode.args() {
+            args.clear();
+        }
+
+        if self.config.dry_run {
+            let envs: Vec<String> = env
+                .iter()
+                .map(|(name, value)| format!("{}={:?}", name, value))
+                .chain(args.iter().map(|arg| format!("{:?}", arg)))
+                .collect();
+            info!(
+                "Dry run, not running trigger scripts: {} ({})",
+                script_paths.join(", "),
+                envs.join(" "),
+            );
+            if let Some(stdin) = &stdin {
+                info!("Dry run, trigger script stdin: {}", stdin);
+            }
+            return;
+        }
+
+        self.triggers.queue(TriggerJob {
+            iface_name: iface_name.to_owned(),
+            family,
+            script_paths,
+            env,
+            args,
+            stdin,
+            timeout: self.config.script_timeout,
+            stop_on_error: self.config.stop_on_error,
+            retry_count: self.config.retry_count,
+            retry_delay: self.config.retry_delay,
+            done: (!history_ids.is_empty())
+                .then(|| self.history_trigger_done(iface_name, history_ids)),
+        });
+    }
+
+    /// Marks the changes' trigger as pending in the history, the returned
+    /// callback records the result
+    fn history_trigger_done(
+        &self,
+        iface_name: &str,
+        ids: Vec<u64>,
+    ) -> Box<dyn FnOnce(bool) + Send> {
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        for id in &ids {
+            history.set_trigger(iface_name, *id, TriggerStatus::Pending);
+        }
+        drop(history);
+
+        let history = Arc::clone(&self.history);
+        let iface_name = iface_name.to_owned();
+        Box::new(move |success| {
+            let trigger = if success {
+                TriggerStatus::Succeeded
+            } else {
+                TriggerStatus::Failed
+            };
+            let mut history = history.lock().unwrap_or_else(|e| e.into_inner());
+            for id in ids {
+                history.set_trigger(&iface_name, id, trigger);
+            }
+        })
+    }
+
+    /// The lease change as JSON, for the script's stdin (`--json-stdin`) and
+    /// the webhook
+    fn get_lease_json<T: Serialize>(
+        iface_name: &str,
+        family: &str,
+        lease_params: &T,
+        old_lease_params: Option<&T>,
+        event: LeaseEvent,
+        changed_families: &str,
+    ) -> String {
+        serde_json::json!({
+            "iface": iface_name,
+            "timestamp": unix_time(),
+            "family": family,
+            "event": event.to_string(),
+            "changed_families": changed_families.split_whitespace().collect::<Vec<_>>(),
+            "lease": lease_params,
+            "old_lease": old_lease_params,
+        })
+        .to_string()
+    }
+
+    /// Maps the lease to dhclient-script variables (reason, new_*, old_*)
+    fn get_dhclient_env(
+        lease_params: &LeaseParams,
+        old_lease_params: Option<&LeaseParams>,
+        event: LeaseEvent,
+    ) -> Vec<(&'static str, String)> {
+        let reason = match event {
+            LeaseEvent::Bound => "BOUND",
+            LeaseEvent::Renew | LeaseEvent::Changed => "RENEW",
+            LeaseEvent::Down => "EXPIRE",
+            // Still held, dhclient rebinds when the renewal failed
+            LeaseEvent::Expiring => "REBIND",
+        };
+
+        let (old_ip_addr, old_route_addr) = match old_lease_params {
+            Some(old) => (lease::or_empty(old.ip_addr), old.route_addr.to_owned()),
+            None => (String::from(""), String::from("")),
+        };
+
+        vec![
+            ("reason", reason.to_string()),
+            ("interface", lease_params.iface_name.to_owned()),
+            ("new_ip_address", lease::or_empty(lease_params.ip_addr)),
+            ("new_routers", lease_params.route_addr.to_owned()),
+            ("old_ip_address", old_ip_addr),
+            ("old_routers", old_route_addr),
+            (
+                "new_domain_name_servers",
+                lease::join_addrs(&lease_params.dns_servers, " "),
+            ),
+            (
+                "old_domain_name_servers",
+                old_lease_params
+                    .map(|old| lease::join_addrs(&old.dns_servers, " "))
+                    .unwrap_or_default(),
+            ),
+        ]
+    }
+
+    /// Maps the lease (IPv6) to dhclient-script variables (reason, new_*, old_*)
+    fn get_dhclient6_env(
+        lease_params: &Lease6Params,
+        old_lease_params: Option<&Lease6Params>,
+        event: LeaseEvent,
+    ) -> Vec<(&'static str, String)> {
+        let reason = match event {
+            LeaseEvent::Bound => "BOUND6",
+            LeaseEvent::Renew | LeaseEvent::Changed => "RENEW6",
+            LeaseEvent::Down => "EXPIRE6",
+            LeaseEvent::Expiring => "REBIND6",
+        };
+
+        vec![
+            ("reason", reason.to_string()),
+            ("interface", lease_params.iface_name.to_owned()),
+            ("new_ip6_prefix", lease_params.ip6_prefix_cidr()),
+            (
+                "old_ip6_prefix",
+                old_lease_params
+                    .map(Lease6Params::ip6_prefix_cidr)
+                    .unwrap_or_default(),
+            ),
+            (
+                "new_dhcp6_name_servers",
+                lease::join_addrs(&lease_params.dns_servers, " "),
+            ),
+            (
+                "old_dhcp6_name_servers",
+                old_lease_params
+                    .map(|old| lease::join_addrs(&old.dns_servers, " "))
+                    .unwrap_or_default(),
+            ),
+        ]
+    }
+
+    /// Execute a daemon lifecycle script (init/cleanup) or a scan hook
+    /// (pre/post), killed after the script timeout; returns true on success,
+    /// or when skipped (shadow mode, dry run)
+    pub fn run_hook_script(&self, script_path: &str, hook: &str, envs: &[(&str, String)]) -> bool {
+        if self.config.shadow {
+            info!("Shadow mode, not running {} script: {}", hook, script_path);
+            return true;
+        }
+        if self.config.dry_run {
+            info!("Dry run, not running {} script: {}", hook, script_path);
+            return true;
+        }
+
+        debug!("Running {} script: {}", hook, script_path);
+
+        let prefix = &self.config.env_prefix;
+        let mut command = Command::new(script_path);
+        command
+            .env(trigger::env_name(prefix, "DHCP_HOOK"), hook)
+            .env(
+                trigger::env_name(prefix, "DHCP_DAEMON_PID"),
+                process::id().to_string(),
+            )
+            .env(
+                trigger::env_name(prefix, "DHCP_INTERFACES"),
+                self.config.interface_names().join(" "),
+            )
+            .envs(
+                envs.iter()
+                    .map(|(name, value)| (trigger::env_name(prefix, name), value)),
+            );
+        // Run on the scan thread, a hanging hook must not stall the scans
+        let output = trigger::output_with_timeout(
+            &mut command,
+            None,
+            Duration::from_secs(self.config.script_timeout),
+        );
+
+        match output {
+            Ok(Some(output)) if output.status.success() => true,
+            Ok(Some(output)) => {
+                warn!(
+                    "{} script execution was unsuccessful: {} (path: {})",
+                    hook, output.status, script_path,
+                );
+                false
+            }
+            Ok(None) => {
+                error!(
+                    "{} ({} script)",
+                    MonitorError::ScriptTimeout {
+                        path: script_path.to_owned(),
+                        timeout: self.config.script_timeout,
+                    },
+                    hook,
+                );
+                false
+            }
+            Err(e) => {
+                error!(
+                    "Failed to execute {} script: {} (path: {})",
+                    hook, e, script_path,
+                );
+                false
+            }
+        }
+    }
+
+    /// Gathers all params related to the lease associated with an interface,
+    /// down if the lease file holds no lease
+    fn get_actual_lease_params(&mut self, iface_name: &str) -> Result<LeaseParams, MonitorError> {
+        let lease_file_path = self.get_lease_file_path(iface_name);
+        let lease = self.read_complete_lease_file(&lease_file_path, |content| {
+            self.lease_source
+                .lease(iface_name, &lease_file_path, content)
+        })?;
+        let Some(lease) = lease else {
+            self.lease_expiry.remove(iface_name);
+            return Ok(LeaseParams::down(iface_name));
+        };
+        let (route_addr, route_match) = match self.config.route_lookup {
+            RouteLookup::Lease => match lease.router {
+                Some(router) => (router.to_string(), String::from("lease")),
+                None => (String::from(""), String::from("")),
+            },
+            _ => self
+                .get_default_route(iface_name, "inet")?
+                .unwrap_or((String::from(""), String::from(""))),
+        };
+
+        match lease.expiry {
+            Some(expiry) => self.lease_expiry.insert(iface_name.to_owned(), expiry),
+            None => self.lease_expiry.remove(iface_name),
+        };
+
+        Ok(LeaseParams {
+            iface_name: iface_name.to_string(),
+            ip_addr: lease.ip_addr,
+            route_addr,
+            route_match,
+            next_server: lease.next_server,
+            filename: lease.filename,
+            host_name: lease.host_name,
+            domain_name: lease.domain_name,
+            dns_servers: lease.dns_servers,
+            lease_time: lease.lease_time,
+        })
+    }
+
+    /// Gathers all params related to the lease associated with an interface,
+    /// down if the lease file holds no lease
+    fn get_actual_lease6_params(&mut self, iface_name: &str) -> Result<Lease6Params, MonitorError> {
+        let lease_file_path = self.get_lease6_file_path(iface_name);
+        let lease6 = self.read_complete_lease_file(&lease_file_path, |content| {
+            self.lease_source
+                .lease6(iface_name, &lease_file_path, content)
+        })?;
+        let Some(lease6) = lease6 else {
+            return Ok(Lease6Params::down(iface_name));
+        };
+        let Lease6 {
+            ip6_prefixes,
+            ip6_addr,
+            dns_servers,
+        } = lease6;
+        let (ip6_prefix, ip6_prefix_len) = ip6_prefixes
+            .first()
+            .map(|(ip6_prefix, ip6_prefix_len)| (Some(*ip6_prefix), Some(*ip6_prefix_len)))
+            .unwrap_or_default();
+        let (route6_addr, route6_match) = self
+            .get_default_route(iface_name, "inet6")?
+            .unwrap_or((String::from(""), String::from("")));
+
+        Ok(Lease6Params {
+            iface_name: iface_name.to_string(),
+            ip6_prefix,
+            ip6_prefix_len,
+            route6_addr,
+            route6_match,
+            ip6_prefixes,
+            ip6_addr,
+            dns_servers,
+        })
+    }
+
+    /// Returns the new lease params if the lease changed and a trigger is due
+    fn check_lease(
+        &mut self,
+        iface_name: &str,
+    ) -> Result<Option<(LeaseEvent, LeaseParams)>, MonitorError> {
+        trace!("Checking (IPv4): {}", iface_name);
+
+        let lease_file_path = self.get_lease_file_path(iface_name);
+        if !Path::new(&lease_file_path).exists() {
+            if !self.lease_params.contains_key(iface_name) {
+                debug!("No lease file for {}", iface_name);
+                return Ok(None);
+            }
+            info!("Lease file removed: {}", lease_file_path);
+            self.forget_file(&lease_file_path);
+            return Ok(Some((LeaseEvent::Down, LeaseParams::down(iface_name))));
+        }
+
+        let modified = match self.check_file_modified(&lease_file_path) {
+            // Removed in between, seen as removed on the next scan
+            Err(e) if e.is_missing_lease_file() => return Ok(None),
+            result => result?,
+        };
+        if self.check_change_due(&lease_file_path, modified) {
+            let lease_params = match self.get_actual_lease_params(iface_name) {
+                Ok(lease_params) => lease_params,
+                Err(
+                    e @ (MonitorError::InvalidLease { .. }
+                    | MonitorError::IncompleteLeaseFile { .. }),
+                ) => {
+                    // Not checked again until the lease file is rewritten
+                    warn!("{}, not triggering until it is rewritten", e);
+                    self.metrics.lease_error(iface_name);
+                    return Ok(None);
+                }
+                Err(e) => {
+                    // Check it again on the next scan
+                    self.forget_file(&lease_file_path);
+                    if e.is_missing_lease_file() {
+                        return Ok(None);
+                    }
+                    return Err(e);
+                }
+            };
+
+            let event = match self.lease_params.get(iface_name) {
+                Some(_) if lease_params.is_down() => LeaseEvent::Down,
+                Some(current_lease_params) if *current_lease_params == lease_params => {
+                    LeaseEvent::Renew
+                }
+                Some(_) => LeaseEvent::Changed,
+                // Nothing to take down without a lease
+                None if lease_params.is_down() => {
+                    debug!("No lease for {}", iface_name);
+                    return Ok(None);
+                }
+                None => LeaseEvent::Bound,
+            };
+
+            if event == LeaseEvent::Renew && !self.config.trigger_on_renew {
+                debug!("Lease params unchanged: {:?}", lease_params);
+            } else {
+                debug!("Lease {}: {:?}", event, lease_params);
+                return Ok(Some((event, lease_params)));
+            }
+        } else {
+            debug!("File not modified for {}", iface_name);
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the new lease params if the lease changed and a trigger is due
+    fn check_lease6(
+        &mut self,
+        iface_name: &str,
+    ) -> Result<Option<(LeaseEvent, Lease6Params)>, MonitorError> {
+        trace!("Checking (IPv6): {}", iface_name);
+
+        let lease_file_path = self.get_lease6_file_path(iface_name);
+        if !Path::new(&lease_file_path).exists() {
+            if !self.lease6_params.contains_key(iface_name) {
+                debug!("No lease file for {}", iface_name);
+                return Ok(None);
+            }
+            info!("Lease file removed: {}", lease_file_path);
+            self.forget_file(&lease_file_path);
+            return Ok(Some((LeaseEvent::Down, Lease6Params::down(iface_name))));
+        }
+
+        let modified = match self.check_file_modified(&lease_file_path) {
+            // Removed in between, seen as removed on the next scan
+            Err(e) if e.is_missing_lease_file() => return Ok(None),
+            result => result?,
+        };
+        if self.check_change_due(&lease_file_path, modified) {
+            let lease6_params = match self.get_actual_lease6_params(iface_name) {
+                Ok(lease6_params) => lease6_params,
+                Err(
+                    e @ (MonitorError::InvalidLease { .. }
+                    | MonitorError::IncompleteLeaseFile { .. }),
+                ) => {
+                    // Not checked again until the lease file is rewritten
+                    warn!("{}, not triggering until it is rewritten", e);
+                    self.metrics.lease_error(iface_name);
+                    return Ok(None);
+                }
+                Err(e) => {
+                    // Check it again on the next scan
+                    self.forget_file(&lease_file_path);
+                    if e.is_missing_lease_file() {
+                        return Ok(None);
+                    }
+                    return Err(e);
+                }
+            };
+
+            let event = match self.lease6_params.get(iface_name) {
+                Some(_) if lease6_params.is_down() => LeaseEvent::Down,
+                Some(current_lease6_params) if *current_lease6_params == lease6_params => {
+                    LeaseEvent::Renew
+                }
+                Some(_) => LeaseEvent::Changed,
+                // Nothing to take down without a lease
+                None if lease6_params.is_down() => {
+                    debug!("No lease for {}", iface_name);
+                    return Ok(None);
+                }
+                None => LeaseEvent::Bound,
+            };
+
+            if event == LeaseEvent::Renew && !self.config.trigger_on_renew {
+                debug!("Lease params unchanged: {:?}", lease6_params);
+            } else {
+                debug!("Lease {}: {:?}", event, lease6_params);
+                return Ok(Some((event, lease6_params)));
+            }
+        } else {
+            debug!("File not modified for {}", iface_name);
+        }
+
+        Ok(None)
+    }
+
+    /// Checks all lease families of an interface, returns the changes to
+    /// trigger (the leases found at startup are only recorded without
+    /// `--trigger-on-startup`)
+    fn check_interface(
+        &mut self,
+        iface_name: &str,
+    ) -> Result<Option<InterfaceChange>, MonitorError> {
+        let rediscovered = self.unpark_leases(iface_name);
+        let mut lease_params = self.check_lease(iface_name)?;
+        let mut lease6_params = if self.config.ipv6 {
+            self.check_lease6(iface_name)?
+        } else {
+            None
+        };
+
+        // Read again (its file stamps were forgotten), the same lease is
+        // only a discovery artifact (a renewal, with `--trigger-on-renew`)
+        if rediscovered {
+            if matches!(lease_params, Some((LeaseEvent::Renew, _))) {
+                info!("Rediscovered {} with its recorded lease", iface_name);
+                lease_params = None;
+            }
+            if matches!(lease6_params, Some((LeaseEvent::Renew, _))) {
+                info!("Rediscovered {} with its recorded IPv6 lease", iface_name);
+                lease6_params = None;
+            }
+        }
+
+        // Also the leases that differ from the state file, and the ones that
+        // are gone since (their recorded lease is dropped)
+        if self.startup && !self.config.trigger_on_startup {
+            if let Some((_, lease_params)) = lease_params {
+                info!("Recorded at startup: {:?}", lease_params);
+                if lease_params.is_down() {
+                    self.lease_params.remove(iface_name);
+                } else {
+                    self.lease_params
+                        .insert(iface_name.to_owned(), lease_params);
+                }
+                self.state_changed = true;
+            }
+            if let Some((_, lease6_params)) = lease6_params {
+                info!("Recorded at startup: {:?}", lease6_params);
+                if lease6_params.is_down() {
+                    self.lease6_params.remove(iface_name);
+                } else {
+                    self.lease6_params
+                        .insert(iface_name.to_owned(), lease6_params);
+                }
+                self.state_changed = true;
+            }
+            return Ok(None);
+        }
+
+        if lease_params.is_none() && lease6_params.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(InterfaceChange {
+            iface_name: iface_name.to_owned(),
+            lease_params,
+            lease6_params,
+        }))
+    }
+
+    /// Records the change in the history (`--history-size`), returns its id
+    fn record_history(&self, iface_name: &str, entry: HistoryEntry) -> Option<u64> {
+        if self.config.history_size == 0 {
+            return None;
+        }
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        Some(history.record(iface_name, entry, self.config.history_size))
+    }
+
+    /// The IPv6 lease in one word: the delegated prefix, or else the address
+    fn get_lease6_summary(lease_params: &Lease6Params) -> String {
+        match lease_params.ip6_prefix {
+            None => lease::or_empty(lease_params.ip6_addr),
+            Some(_) => lease_params.ip6_prefix_cidr(),
+        }
+    }
+
+    /// Logs a trigger: as a journal entry with the given fields (`--journal`),
+    /// or as usual if the journal is not available
+    fn log_trigger(&self, message: &str, fields: &[(&str, &str)]) {
+        if self.config.journal {
+            let mut entry = vec![
+                ("MESSAGE", message),
+                ("PRIORITY", "6"),
+                ("SYSLOG_IDENTIFIER", env!("CARGO_PKG_NAME")),
+            ];
+            entry.extend_from_slice(fields);
+            match systemd::journal_send(&entry) {
+                Ok(()) => return,
+                Err(e) => debug!("Failed to write to the journal: {}", e),
+            }
+        }
+        info!("{}", message);
+    }
+
+    /// Runs the triggers of the interface's changes, returns the number of
+    /// triggers run
+    fn trigger_interface(&mut self, change: InterfaceChange) -> usize {
+        let changed_families = change.families().join(" ");
+        let iface_name = change.iface_name.as_str();
+
+        // Both changes trigger the IPv4 script once (`--unified-trigger`)
+        let unified = self.config.unified_trigger
+            && change.lease_params.is_some()
+            && change.lease6_params.is_some();
+        let mut unified_inet = None;
+
+        // The new state is recorded as soon as the scripts are queued, a
+        // script failing later does not undo it (the lease is not retriggered)
+        let mut triggered = 0;
+        if let Some((event, lease_params)) = change.lease_params {
+            self.log_trigger(
+                &format!("Triggered ({}): {:?}", event, lease_params),
+                &[
+                    ("INTERFACE", iface_name),
+                    ("FAMILY", "inet"),
+                    ("EVENT", &event.to_string()),
+                    ("IP_ADDRESS", &lease::or_empty(lease_params.ip_addr)),
+                    ("ROUTE", &lease_params.route_addr),
+                ],
+            );
+            self.metrics.triggered(iface_name);
+            let old_lease_params = self.lease_params.get(iface_name).cloned();
+            let history_id = self.record_history(
+                iface_name,
+                HistoryEntry::new(
+                    "inet",
+                    event.to_string(),
+                    old_lease_params
+                        .as_ref()
+                        .map(|old| lease::or_empty(old.ip_addr))
+                        .unwrap_or_default(),
+                    lease::or_empty(lease_params.ip_addr),
+                ),
+            );
+            self.notify_webhook(Self::get_lease_json(
+                iface_name,
+                "inet",
+                &lease_params,
+                old_lease_params.as_ref(),
+                event,
+                &changed_families,
+            ));
+            if self.config.mqtt_broker.is_some() {
+                let env = self.get_trigger_env(
+                    &lease_params,
+                    old_lease_params.as_ref(),
+                    event,
+                    &changed_families,
+                );
+                self.publish_mqtt(iface_name, "ipv4", &env);
+            }
+            if unified {
+                unified_inet = Some((
+                    lease_params.clone(),
+                    old_lease_params.clone(),
+                    event,
+                    history_id,
+                ));
+            } else {
+                self.run_trigger_script(
+                    &lease_params,
+                    old_lease_params.as_ref(),
+                    event,
+                    &changed_families,
+                    history_id,
+                );
+            }
+            if lease_params.is_down() {
+                self.lease_params.remove(iface_name);
+            } else {
+                self.lease_params
+                    .insert(iface_name.to_owned(), lease_params);
+            }
+            self.state_changed = true;
+            triggered += 1;
+        }
+
+        if let Some((event, lease6_params)) = change.lease6_params {
+            let ip_prefix = lease6_params.ip6_prefix_cidr();
+            self.log_trigger(
+                &format!("Triggered ({}): {:?}", event, lease6_params),
+                &[
+                    ("INTERFACE", iface_name),
+                    ("FAMILY", "inet6"),
+                    ("EVENT", &event.to_string()),
+                    ("IP_ADDRESS", &lease::or_empty(lease6_params.ip6_addr)),
+                    ("IP_PREFIX", &ip_prefix),
+                    ("ROUTE", &lease6_params.route6_addr),
+                ],
+            );
+            self.metrics.triggered(iface_name);
+            let old_lease6_params = self.lease6_params.get(iface_name).cloned();
+            let history_id = self.record_history(
+                iface_name,
+                HistoryEntry::new(
+                    "inet6",
+                    event.to_string(),
+                    old_lease6_params
+                        .as_ref()
+                        .map(Self::get_lease6_summary)
+                        .unwrap_or_default(),
+                    Self::get_lease6_summary(&lease6_params),
+                ),
+            );
+            self.notify_webhook(Self::get_lease_json(
+                iface_name,
+                "inet6",
+                &lease6_params,
+                old_lease6_params.as_ref(),
+                event,
+                &changed_families,
+            ));
+            if self.config.mqtt_broker.is_some() {
+                let env = self.get_trigger6_env(
+                    &lease6_params,
+                    old_lease6_params.as_ref(),
+                    event,
+                    &changed_families,
+                );
+                self.publish_mqtt(iface_name, "ipv6", &env);
+            }
+            match unified_inet.take() {
+                Some((lease_params, old_lease_params, event4, history4_id)) => {
+                    self.run_trigger_script_dual(
+                        (&lease_params, old_lease_params.as_ref(), event4),
+                        (&lease6_params, old_lease6_params.as_ref(), event),
+                        &changed_families,
+                        history4_id.into_iter().chain(history_id).collect(),
+                    );
+                }
+                None => self.run_trigger_script_ipv6(
+                    &lease6_params,
+                    old_lease6_params.as_ref(),
+                    event,
+                    &changed_families,
+                    history_id,
+                ),
+            }
+            if lease6_params.is_down() {
+                self.lease6_params.remove(iface_name);
+            } else {
+                self.lease6_params
+                    .insert(iface_name.to_owned(), lease6_params);
+            }
+            self.state_changed = true;
+            triggered += 1;
+        }
+
+        if triggered > 0 {
+            self.last_change.insert(iface_name.to_owned(), unix_time());
+        }
+        self.last_triggered
+            .insert(iface_name.to_owned(), Instant::now());
+        triggered
+    }
+
+    /// Holds back the changes of the interfaces still in their cooldown
+    /// (`--cooldown`), merged with their earlier held back changes. Returns
+    /// the changes due: those of interfaces not in their cooldown, followed
+    /// by the held back ones whose cooldown passed.
+    fn apply_cooldown(&mut self, changes: Vec<InterfaceChange>) -> Vec<InterfaceChange> {
+        let now = Instant::now();
+        let cooldown = self.config.cooldown;
+        let in_cooldown = |last_triggered: &HashMap<String, Instant>, iface_name: &str| {
+            last_triggered
+                .get(iface_name)
+                .is_some_and(|triggered| now.duration_since(*triggered) < cooldown)
+        };
+
+        let mut due = Vec::new();
+        for mut change in changes {
+            if let Some(mut deferred) = self.deferred_changes.remove(&change.iface_name) {
+                deferred.merge(change);
+                change = deferred;
+            }
+            if in_cooldown(&self.last_triggered, &change.iface_name) {
+                info!(
+                    "Lease change of {} in cooldown, trigger postponed",
+                    change.iface_name
+                );
+                self.deferred_changes
+                    .insert(change.iface_name.to_owned(), change);
+            } else {
+                due.push(change);
+            }
+        }
+
+        let expired: Vec<String> = self
+            .deferred_changes
+            .keys()
+            .filter(|iface_name| !in_cooldown(&self.last_triggered, iface_name))
+            .cloned()
+            .collect();
+        for iface_name in expired {
+            if let Some(change) = self.deferred_changes.remove(&iface_name) {
+                due.push(change);
+            }
+        }
+
+        due
+    }
+
+    /// POSTs the lease change to the webhook (if any)
+    fn notify_webhook(&mut self, payload: String) {
+        let Some(url) = &self.config.webhook_url else {
+            return;
+        };
+
+        // Shadow mode only holds back the scripts
+        if self.config.dry_run {
+            info!("Dry run, not sending webhook: {} ({})", url, payload);
+            return;
+        }
+
+        // Replaced if the URL was changed by a reload, the old worker still
+        // sends what is queued
+        if self
+            .webhook
+            .as_ref()
+            .is_none_or(|webhook| webhook.url() != url)
+        {
+            self.webhook = match Webhook::spawn(url) {
+                Ok(webhook) => Some(webhook),
+                Err(e) => {
+                    error!("Failed to start webhook worker for {}: {}", url, e);
+                    return;
+                }
+            };
+        }
+        if let Some(webhook) = &self.webhook {
+            webhook.send(payload);
+        }
+    }
+
+    /// Publishes the lease change (the trigger script environment) to the
+    /// MQTT broker (if any)
+    fn publish_mqtt(&mut self, iface_name: &str, family: &str, env: &ScriptEnv) {
+        let Some(broker) = &self.config.mqtt_broker else {
+            return;
+        };
+
+        let payload = match serde_json::to_string(env) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize MQTT message: {}", e);
+                return;
+            }
+        };
+
+        // Shadow mode only holds back the scripts
+        if self.config.dry_run {
+            info!("Dry run, not publishing to MQTT: {} ({})", broker, payload);
+            return;
+        }
+
+        if self.mqtt.is_none() {
+            self.mqtt = match Mqtt::spawn(
+                broker,
+                &self.config.mqtt_topic_prefix,
+                self.config.mqtt_username.as_deref(),
+                self.config.mqtt_password.as_deref(),
+            ) {
+                Ok(mqtt) => Some(mqtt),
+                Err(e) => {
+                    error!("Failed to connect to MQTT broker {}: {}", broker, e);
+                    return;
+                }
+            };
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.publish(iface_name, family, payload);
+        }
+    }
+
+    /// The current leases of every interface as renewals (on SIGUSR1),
+    /// leaving out the interfaces already changed in this scan
+    fn get_retriggered_changes(&self, changes: &[InterfaceChange]) -> Vec<InterfaceChange> {
+        info!("Re-running the triggers of all current leases");
+        let iface_names: BTreeSet<&String> = self
+            .lease_params
+            .keys()
+            .chain(self.lease6_params.keys())
+            .filter(|iface_name| {
+                !changes
+                    .iter()
+                    .any(|change| &change.iface_name == *iface_name)
+            })
+            .collect();
+        iface_names
+            .into_iter()
+            .map(|iface_name| InterfaceChange {
+                iface_name: iface_name.to_owned(),
+                lease_params: self
+                    .lease_params
+                    .get(iface_name)
+                    .map(|lease_params| (LeaseEvent::Renew, lease_params.clone())),
+                lease6_params: self
+                    .lease6_params
+                    .get(iface_name)
+                    .map(|lease6_params| (LeaseEvent::Renew, lease6_params.clone())),
+            })
+            .collect()
+    }
+
+    /// Finds the leases expiring within `--expiry-warning` (once per lease),
+    /// leaving out the interfaces already changed in this scan
+    fn check_expiry(&mut self, changes: &[InterfaceChange]) -> Vec<InterfaceChange> {
+        let now = unix_time();
+        // The expiry is in wall clock time, meaningless until the clock is set
+        if self.config.expiry_warning.is_zero() || now < CLOCK_SANE_MIN_SECS {
+            return Vec::new();
+        }
+        let warning = self.config.expiry_warning.as_secs();
+
+        let mut expiring = Vec::new();
+        for (iface_name, &expiry) in &self.lease_expiry {
+            if now.saturating_add(warning) < expiry
+                || self.expiry_warned.get(iface_name) == Some(&expiry)
+                || changes
+                    .iter()
+                    .any(|change| &change.iface_name == iface_name)
+            {
+                continue;
+            }
+            let Some(lease_params) = self.lease_params.get(iface_name) else {
+                continue;
+            };
+            self.expiry_warned.insert(iface_name.to_owned(), expiry);
+
+            if expiry <= now {
+                // Passed in between scans, too late to warn
+                warn!(
+                    "Lease of {} expired {} ago without being renewed",
+                    iface_name,
+                    humantime::format_duration(Duration::from_secs(now - expiry))
+                );
+                continue;
+            }
+            info!(
+                "Lease of {} expires in {}",
+                iface_name,
+                humantime::format_duration(Duration::from_secs(expiry - now))
+            );
+            expiring.push(InterfaceChange {
+                iface_name: iface_name.to_owned(),
+                lease_params: Some((LeaseEvent::Expiring, lease_params.clone())),
+                lease6_params: None,
+            });
+        }
+        expiring
+    }
+
+    /// Checks every monitored interface once and runs the triggers, between
+    /// the pre- and post-trigger hooks
+    fn scan_once(&mut self) -> ScanSummary {
+        let mut summary = ScanSummary::default();
+        let started = Instant::now();
+
+        // Routes are looked up at most once per scan
+        self.route_tables.clear();
+
+        // Taken for the scan rather than cloned, checking borrows self
+        self.update_monitored_interfaces();
+        let iface_names = std::mem::take(&mut self.monitored_interfaces);
+        let mut changes = Vec::new();
+        for iface_name in &iface_names {
+            match self.check_interface(iface_name) {
+                Ok(Some(change)) => changes.push(change),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Failed to check {}: {}", iface_name, e);
+                    self.metrics.lease_error(iface_name);
+                    summary.errors += 1;
+                }
+            }
+        }
+        self.monitored_interfaces = iface_names;
+        let mut changes = self.take_postponed_changes(changes);
+
+        let expiring = self.check_expiry(&changes);
+        changes.extend(expiring);
+        if self.retrigger.swap(false, Ordering::Relaxed) {
+            let retriggered = self.get_retriggered_changes(&changes);
+            changes.extend(retriggered);
+        }
+
+        let changes = self.apply_cooldown(changes);
+        if !changes.is_empty() {
+            let hook_env = Self::get_scan_hook_env(&changes);
+            if self.run_pre_hook(&hook_env) {
+                for change in changes {
+                    summary.triggered += self.trigger_interface(change);
+                }
+                if let Some(post_hook) = self.config.post_hook.clone() {
+                    // It runs once the triggers of the scan have completed
+                    self.triggers.wait();
+                    self.run_hook_script(&post_hook, "post", &hook_env);
+                }
+            } else {
+                warn!("Pre-trigger hook failed, triggers postponed to the next scan");
+                summary.errors += 1;
+                self.postponed_changes.extend(
+                    changes
+                        .into_iter()
+                        .map(|change| (change.iface_name.clone(), change)),
+                );
+            }
+        }
+
+        // The triggers fail or recover after the scan that queued them, the
+        // status catches up on the next one
+        let failed_interfaces = self.metrics.failed_interfaces();
+        if self.state_changed || self.startup || failed_interfaces != self.failed_interfaces {
+            self.failed_interfaces = failed_interfaces;
+            self.update_status();
+        }
+        self.startup = false;
+        self.save_state();
+
+        let duration = started.elapsed();
+        let stats = self.metrics.scanned(duration);
+        debug!(
+            "Scan took {:?} (last {} scans: min {:?}, avg {:?}, max {:?})",
+            duration, stats.count, stats.min, stats.avg, stats.max,
+        );
+        if duration > self.config.interval {
+            warn!(
+                "Scan took {:?}, longer than the scan interval ({}), falling behind",
+                duration,
+                humantime::format_duration(self.config.interval),
+            );
+        }
+
+        summary
+    }
+
+    /// Runs the pre-trigger hook (if any), returns whether the triggers may
+    /// run: always, unless the hook failed with `--pre-hook-required`
+    fn run_pre_hook(&self, hook_env: &[(&str, String)]) -> bool {
+        match &self.config.pre_hook {
+            Some(pre_hook) => {
+                self.run_hook_script(pre_hook, "pre", hook_env) || !self.config.pre_hook_required
+            }
+            None => true,
+        }
+    }
+
+    /// Environment of the pre- and post-trigger hooks: the changed interfaces
+    fn get_scan_hook_env(changes: &[InterfaceChange]) -> Vec<(&'static str, String)> {
+        let iface_names = |family: Option<&str>| {
+            changes
+                .iter()
+                .filter(|change| family.is_none_or(|family| change.families().contains(&family)))
+                .map(|change| change.iface_name.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        vec![
+            ("DHCP_CHANGED_INTERFACES", iface_names(None)),
+            ("DHCP_CHANGED_INET", iface_names(Some("inet"))),
+            ("DHCP_CHANGED_INET6", iface_names(Some("inet6"))),
+        ]
+    }
+
+    /// Adds the changes postponed by a failed pre-trigger hook to those of
+    /// the scan, merged with the later changes of their interfaces
+    fn take_postponed_changes(&mut self, changes: Vec<InterfaceChange>) -> Vec<InterfaceChange> {
+        let mut postponed = std::mem::take(&mut self.postponed_changes);
+        let mut changes: Vec<InterfaceChange> = changes
+            .into_iter()
+            .map(|change| match postponed.remove(&change.iface_name) {
+                Some(mut earlier) => {
+                    earlier.merge(change);
+                    earlier
+                }
+                None => change,
+            })
+            .collect();
+        changes.extend(postponed.into_values());
+        changes
+    }
+
+    /// The main monitoring loop, runs until SIGTERM/SIGINT
+    pub fn run(&mut self) {
+        systemd::notify_ready();
+
+        while !self.shutdown_requested() {
+            if self.reload.swap(false, Ordering::Relaxed) {
+                self.reload_config();
+            }
+
+            let scan_started = Instant::now();
+            self.scan_once();
+            systemd::notify_watchdog();
+            self.sleep_interval(scan_started);
+        }
+
+        info!("Shutting down");
+        systemd::notify_stopping();
+        self.triggers.wait();
+        self.wait_notifiers();
+    }
+
+    /// A single scan for `--oneshot`: waits for the triggers to complete and
+    /// returns the exit status (0: no change, 1: errors, 2: triggers run)
+    pub fn run_once(&mut self) -> i32 {
+        let summary = self.scan_once();
+        self.triggers.wait();
+        self.wait_notifiers();
+
+        if summary.errors > 0 || self.triggers.failed() > 0 {
+            1
+        } else if summary.triggered > 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    /// Updates the interfaces to check in this scan: the configured ones and
+    /// the ones that have a lease file matching an interface pattern (or any
+    /// lease file, with auto-discovery) or still have a recorded lease (so
+    /// that the removal of its lease file is noticed). Without discovery the
+    /// list is kept as it is.
+    fn update_monitored_interfaces(&mut self) {
+        if !self.config.auto_discover && !self.config.has_interface_patterns() {
+            return;
+        }
+        let mut iface_names = self.config.interface_names();
+
+        let mut discovered = discover_interfaces(
+            &self.config.dhcp_lease_dir,
+            &self.config.lease_file_template,
+        );
+        if self.config.ipv6 {
+            discovered.extend(discover_interfaces(
+                &self.config.dhcp6_lease_dir,
+                &self.config.lease6_file_template,
+            ));
+        }
+        for extra in &self.config.extra_lease_dir {
+            match extra.proto.as_str() {
+                "inet6" if !self.config.ipv6 => {}
+                "inet6" => discovered.extend(discover_interfaces(
+                    &extra.dir,
+                    &self.config.lease6_file_template,
+                )),
+                _ => discovered.extend(discover_interfaces(
+                    &extra.dir,
+                    &self.config.lease_file_template,
+                )),
+            }
+        }
+        discovered.extend(self.lease_params.keys().cloned());
+        discovered.extend(self.lease6_params.keys().cloned());
+        discovered.retain(|iface_name| {
+            (self.config.auto_discover || self.config.matches_interface_pattern(iface_name))
+                && !self.config.is_ignored(iface_name)
+        });
+
+        for iface_name in discovered.difference(&self.discovered_interfaces) {
+            info!("Discovered interface: {}", iface_name);
+        }
+        let lost: Vec<String> = self
+            .discovered_interfaces
+            .difference(&discovered)
+            .filter(|iface_name| !iface_names.contains(iface_name))
+            .cloned()
+            .collect();
+        for iface_name in lost {
+            debug!("Interface no longer discovered: {}", iface_name);
+            self.drop_interface(&iface_name);
+        }
+
+        for iface_name in &discovered {
+            if !iface_names.contains(iface_name) {
+                iface_names.push(iface_name.to_owned());
+            }
+        }
+
+        self.discovered_interfaces = discovered;
+        self.monitored_interfaces = iface_names;
+    }
+
+    /// Re-reads the configuration (on SIGHUP); state of the interfaces that
+    /// remain monitored is kept, removed interfaces are forgotten
+    fn reload_config(&mut self) {
+        let mut config = match Args::try_parse()
+            .map_err(|e| e.to_string())
+            .and_then(|args| Config::from_args(&args).map_err(|e| e.to_string()))
+        {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Failed to reload configuration: {}", e);
+                return;
+            }
+        };
+        config.check_interfaces();
+
+        for iface_name in self.config.interface_names() {
+            if config.is_ignored(&iface_name) {
+                self.forget_interface(&iface_name);
+            } else if config.interface(&iface_name).is_none() {
+                self.drop_interface(&iface_name);
+            }
+        }
+
+        if config.route_lookup != self.config.route_lookup
+            || config.netstat_path != self.config.netstat_path
+        {
+            self.route_provider = route::provider(config.route_lookup, &config.netstat_path);
+        }
+        if config.lease_format != self.config.lease_format
+            || config.dhcpcd_path != self.config.dhcpcd_path
+        {
+            self.lease_source = lease::source(config.lease_format, &config.dhcpcd_path);
+        }
+        if (
+            &config.mqtt_broker,
+            &config.mqtt_topic_prefix,
+            &config.mqtt_username,
+            &config.mqtt_password,
+        ) != (
+            &self.config.mqtt_broker,
+            &self.config.mqtt_topic_prefix,
+            &self.config.mqtt_username,
+            &self.config.mqtt_password,
+        ) {
+            // Reconnected on next use
+            self.mqtt = None;
+        }
+        log::set_max_level(config.log_level());
+        self.config = config;
+        self.monitored_interfaces = self.config.interface_names();
+
+        info!(
+            "Configuration reloaded: {:?}",
+            self.config.interface_names()
+        );
+    }
+
+    /// Drops all state kept for an interface
+    fn forget_interface(&mut self, iface_name: &str) {
+        self.forget_file(&self.get_lease_file_path(iface_name));
+        self.forget_file(&self.get_lease6_file_path(iface_name));
+        self.lease_params.remove(iface_name);
+        self.lease6_params.remove(iface_name);
+        self.last_change.remove(iface_name);
+        self.last_triggered.remove(iface_name);
+        self.deferred_changes.remove(iface_name);
+        self.postponed_changes.remove(iface_name);
+        self.lease_expiry.remove(iface_name);
+        self.expiry_warned.remove(iface_name);
+        self.triggers.remove(iface_name);
+        self.parked_lease_params.remove(iface_name);
+        self.parked_lease6_params.remove(iface_name);
+        self.state_changed = true;
+    }
+
+    /// Stops monitoring an interface that is no longer selected: it is
+    /// forgotten, but with `--no-trigger-on-rediscovery` its recorded leases
+    /// are kept (and saved to the state file) unless it is ignored
+    fn drop_interface(&mut self, iface_name: &str) {
+        let lease_params = self.lease_params.remove(iface_name);
+        let lease6_params = self.lease6_params.remove(iface_name);
+        self.forget_interface(iface_name);
+        if !self.config.no_trigger_on_rediscovery || self.config.is_ignored(iface_name) {
+            return;
+        }
+        // Parked, so that SIGUSR1 and the status leave them out
+        if let Some(lease_params) = lease_params {
+            self.parked_lease_params
+                .insert(iface_name.to_owned(), lease_params);
+        }
+        if let Some(lease6_params) = lease6_params {
+            self.parked_lease6_params
+                .insert(iface_name.to_owned(), lease6_params);
+        }
+    }
+
+    /// Restores the parked leases of an interface monitored again, returns
+    /// whether it had any
+    fn unpark_leases(&mut self, iface_name: &str) -> bool {
+        let lease_params = self.parked_lease_params.remove(iface_name);
+        let lease6_params = self.parked_lease6_params.remove(iface_name);
+        let rediscovered = lease_params.is_some() || lease6_params.is_some();
+        if let Some(lease_params) = lease_params {
+            self.lease_params
+                .insert(iface_name.to_owned(), lease_params);
+        }
+        if let Some(lease6_params) = lease6_params {
+            self.lease6_params
+                .insert(iface_name.to_owned(), lease6_params);
+        }
+        rediscovered
+    }
+
+    /// Loads the lease params saved by a previous run (if configured)
+    pub fn load_state(&mut self) {
+        if let Some(state_file) = &self.config.state_file {
+            let mut state = state::load(state_file);
+            state
+                .lease_params
+                .retain(|iface_name, _| !self.config.is_ignored(iface_name));
+            state
+                .lease6_params
+                .retain(|iface_name, _| !self.config.is_ignored(iface_name));
+            self.lease_params = state.lease_params;
+            self.lease6_params = state.lease6_params;
+        }
+    }
+
+    /// Saves the lease params (if configured and changed)
+    fn save_state(&mut self) {
+        if !self.state_changed {
+            return;
+        }
+        if let Some(state_file) = &self.config.state_file {
+            // With the parked leases, for when their interfaces return
+            let mut lease_params = self.parked_lease_params.clone();
+            lease_params.extend(self.lease_params.clone());
+            let mut lease6_params = self.parked_lease6_params.clone();
+            lease6_params.extend(self.lease6_params.clone());
+            if let Err(e) = state::save(state_file, &lease_params, &lease6_params) {
+                error!("Failed to save state file {}: {}", state_file, e);
+                return;
+            }
+        }
+        self.state_changed = false;
+    }
+
+    /// Waits for the webhook and MQTT workers to send the queued lease changes
+    fn wait_notifiers(&mut self) {
+        if let Some(webhook) = self.webhook.take() {
+            webhook.wait();
+        }
+        if let Some(mqtt) = self.mqtt.take() {
+            mqtt.wait();
+        }
+    }
+
+    /// Writes the status file (if any) and updates the status answered on
+    /// the control socket
+    fn update_status(&self) {
+        if self.config.status_file.is_none() && self.config.control_socket.is_none() {
+            return;
+        }
+
+        let status = status::snapshot(
+            &self.lease_params,
+            &self.lease6_params,
+            &self.last_change,
+            &self.failed_interfaces,
+        );
+        if let Some(status_file) = &self.config.status_file {
+            if let Err(e) = status::save(status_file, &status) {
+                error!("Failed to save status file {}: {}", status_file, e);
+            }
+        }
+        *self.status.lock().unwrap_or_else(|e| e.into_inner()) = status;
+    }
+
+    /// Sleeps for the scan interval, waking up early on shutdown. With
+    /// `--fixed-rate` the interval counts from the start of the scan, so the
+    /// scan duration is subtracted (no sleep at all if it overran).
+    fn sleep_interval(&mut self, scan_started: Instant) {
+        let interval_start = if self.config.fixed_rate {
+            scan_started
+        } else {
+            Instant::now()
+        };
+        let deadline = interval_start + self.jittered_interval();
+        // SIGUSR1 cuts the sleep short, the triggers are re-run right away
+        while !self.shutdown_requested() && !self.retrigger.load(Ordering::Relaxed) {
+            if self.dump.swap(false, Ordering::Relaxed) {
+                self.dump_state();
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            sleep((deadline - now).min(SHUTDOWN_POLL_INTERVAL));
+        }
+    }
+
+    /// Logs the recorded leases and the last seen state of the lease files
+    /// (on SIGUSR2), for debugging why a trigger did or did not run
+    fn dump_state(&self) {
+        info!(
+            "State dump: {} lease(s), {} IPv6 lease(s), {} lease file(s) seen",
+            self.lease_params.len(),
+            self.lease6_params.len(),
+            self.timestamps.len() + self.hashes.len()
+        );
+        for (iface_name, lease_params) in BTreeMap::from_iter(&self.lease_params) {
+            info!("Lease {}: {:?}", iface_name, lease_params);
+        }
+        for (iface_name, lease6_params) in BTreeMap::from_iter(&self.lease6_params) {
+            info!("IPv6 lease {}: {:?}", iface_name, lease6_params);
+        }
+        for (path, stamp) in BTreeMap::from_iter(&self.timestamps) {
+            info!(
+                "Lease file {}: modified {}, {} bytes, inode {}",
+                path,
+                humantime::format_rfc3339_millis(stamp.modified),
+                stamp.len,
+                stamp.inode
+            );
+        }
+        for (path, hash) in BTreeMap::from_iter(&self.hashes) {
+            info!("Lease file {}: content hash {:016x}", path, hash);
+        }
+        for (path, changed) in BTreeMap::from_iter(&self.pending_changes) {
+            info!(
+                "Lease file {}: change pending for {}",
+                path,
+                humantime::format_duration(Duration::from_millis(
+                    changed.elapsed().as_millis() as u64
+                ))
+            );
+        }
+        for iface_name in self.deferred_changes.keys() {
+            info!("Interface {}: change deferred by the cooldown", iface_name);
+        }
+        for iface_name in self.postponed_changes.keys() {
+            info!(
+                "Interface {}: change postponed by the pre-trigger hook",
+                iface_name
+            );
+        }
+    }
+
+    /// The scan interval, randomized by up to `--jitter` percent of it
+    fn jittered_interval(&mut self) -> Duration {
+        let interval = self.config.interval;
+        if self.config.jitter == 0 {
+            return interval;
+        }
+
+        // xorshift64, good enough to spread the scans
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        // Uniform in [-jitter, +jitter]
+        let factor =
+            (self.rng as f64 / u64::MAX as f64 * 2.0 - 1.0) * f64::from(self.config.jitter) / 100.0;
+        interval.mul_f64(1.0 + factor)
+    }
+
+    /// Registers SIGTERM/SIGINT to stop the main loop, SIGHUP to reload,
+    /// SIGUSR1 to re-run the triggers and SIGUSR2 to dump the state
+    pub fn register_signals(&self) -> io::Result<()> {
+        for signal in [SIGTERM, SIGINT] {
+            signal_hook::flag::register(signal, Arc::clone(&self.shutdown))?;
+        }
+        signal_hook::flag::register(SIGHUP, Arc::clone(&self.reload))?;
+        signal_hook::flag::register(SIGUSR1, Arc::clone(&self.retrigger))?;
+        signal_hook::flag::register(SIGUSR2, Arc::clone(&self.dump))?;
+        Ok(())
+    }
+
+    fn shutdown_requested(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+
+    /// Status answered on the control socket
+    pub fn status(&self) -> SharedStatus {
+        Arc::clone(&self.status)
+    }
+
+    /// Recent lease changes, for the control socket
+    pub fn history(&self) -> SharedHistory {
+        Arc::clone(&self.history)
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+}
+
+/// Seed of the random number generator (never zero, a xorshift fixpoint):
+/// the time and the process id
+fn rng_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_nanos() as u64)
+        .unwrap_or(0);
+    (nanos ^ (u64::from(process::id()) << 32)) | 1
+}
+
+/// Finds the interfaces that have a lease file in the directory: the file
+/// names that match the lease file template and name an existing interface
+fn discover_interfaces(lease_dir: &str, lease_file_template: &str) -> BTreeSet<String> {
+    let entries = match fs::read_dir(lease_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("Failed to read lease directory {}: {}", lease_dir, e);
+            return BTreeSet::new();
+        }
+    };
+
+    let (prefix, suffix) = lease_file_template
+        .split_once("{iface}")
+        .unwrap_or((lease_file_template, ""));
+
+    entries
+        .map_while(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            let iface_name = file_name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            interface_exists(iface_name).then(|| iface_name.to_string())
+        })
+        .collect()
+}
+
+/// Is there a network interface with this name?
+fn interface_exists(iface_name: &str) -> bool {
+    interface_index(iface_name).is_some()
+}
+
+/// Index of the network interface with this name
+pub fn interface_index(iface_name: &str) -> Option<u32> {
+    let c_name = CString::new(iface_name).ok()?;
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => None,
+        index => Some(index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::InterfaceConfig;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn monitor(interfaces: Vec<InterfaceConfig>) -> Monitor {
+        Monitor::new(Config {
+            scripts_dir: String::from("/etc/dhcpleasemon"),
+            interfaces,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn trigger_script_path_uses_prefix() {
+        let mut em1 = InterfaceConfig::new("em1");
+        em1.trigger_script_prefix = Some(String::from("v4_"));
+        em1.trigger_script_prefix_ipv6 = Some(String::from("v6_"));
+        let monitor = monitor(vec![InterfaceConfig::new("em0"), em1]);
+
+        assert_eq!(
+            monitor.get_trigger_script_path("em0"),
+            "/etc/dhcpleasemon/lease_trigger_em0"
+        );
+        assert_eq!(
+            monitor.get_trigger_script_path("em1"),
+            "/etc/dhcpleasemon/v4_em1"
+        );
+        assert_eq!(
+            monitor.get_trigger_script_path_ipv6("em1"),
+            "/etc/dhcpleasemon/v6_em1"
+        );
+    }
+
+    #[test]
+    fn trigger_script_path_uses_interface_script() {
+        let mut em0 = InterfaceConfig::new("em0");
+        em0.trigger_script = Some(String::from("uplink"));
+        em0.trigger_script_ipv6 = Some(String::from("/usr/local/libexec/uplink6"));
+        let monitor = monitor(vec![em0]);
+
+        assert_eq!(
+            monitor.get_trigger_script_path("em0"),
+            "/etc/dhcpleasemon/uplink"
+        );
+        assert_eq!(
+            monitor.get_trigger_script_path_ipv6("em0"),
+            "/usr/local/libexec/uplink6"
+        );
+    }
+
+    #[test]
+    fn interface_change_merge_keeps_earlier_family() {
+        let mut change = InterfaceChange {
+            iface_name: String::from("em0"),
+            lease_params: Some((LeaseEvent::Bound, LeaseParams::down("em0"))),
+            lease6_params: None,
+        };
+        change.merge(InterfaceChange {
+            iface_name: String::from("em0"),
+            lease_params: None,
+            lease6_params: Some((LeaseEvent::Bound, Lease6Params::down("em0"))),
+        });
+        assert_eq!(change.families(), vec!["inet", "inet6"]);
+    }
+
+    #[test]
+    fn dhclient_env_has_name_servers() {
+        let old_lease_params = LeaseParams {
+            iface_name: String::from("em0"),
+            ip_addr: Some(Ipv4Addr::new(192, 0, 2, 10)),
+            dns_servers: vec![Ipv4Addr::new(192, 0, 2, 53)],
+            ..Default::default()
+        };
+        let lease_params = LeaseParams {
+            dns_servers: vec![Ipv4Addr::new(192, 0, 2, 53), Ipv4Addr::new(192, 0, 2, 54)],
+            ..old_lease_params.clone()
+        };
+        let env =
+            Monitor::get_dhclient_env(&lease_params, Some(&old_lease_params), LeaseEvent::Changed);
+        assert!(env.contains(&(
+            "new_domain_name_servers",
+            String::from("192.0.2.53 192.0.2.54")
+        )));
+        assert!(env.contains(&("old_domain_name_servers", String::from("192.0.2.53"))));
+
+        let lease6_params = Lease6Params {
+            iface_name: String::from("em0"),
+            dns_servers: vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53)],
+            ..Default::default()
+        };
+        let env = Monitor::get_dhclient6_env(&lease6_params, None, LeaseEvent::Bound);
+        assert!(env.contains(&("new_dhcp6_name_servers", String::from("2001:db8::53"))));
+        assert!(env.contains(&("old_dhcp6_name_servers", String::new())));
+    }
+
+    /// Selects the discovered interfaces by the glob, or none
+    fn select_interfaces(monitor: &mut Monitor, pattern: &str) {
+        monitor.config.interfaces = vec![InterfaceConfig::new(pattern)];
+        monitor.monitored_interfaces = monitor.config.interface_names();
+    }
+
+    #[test]
+    fn rediscovered_interface_with_the_same_lease_is_not_triggered() {
+        let dir = tempfile::tempdir().unwrap();
+        // Discovery only picks up the lease files of existing interfaces
+        let iface_name = ["lo", "lo0"]
+            .into_iter()
+            .find(|name| interface_exists(name))
+            .unwrap();
+        let lease_dir = dir.path().join("leases");
+        let scripts_dir = dir.path().join("scripts");
+        fs::create_dir(&lease_dir).unwrap();
+        fs::create_dir(&scripts_dir).unwrap();
+        let script_path = scripts_dir.join(format!("lease_trigger_{}", iface_name));
+        fs::write(&script_path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).unwrap();
+        let lease_path = lease_dir.join(iface_name);
+        fs::write(&lease_path, "ip: 192.0.2.10\n").unwrap();
+
+        for (no_trigger_on_rediscovery, trigger_on_renew) in
+            [(false, false), (true, false), (true, true)]
+        {
+            let mut monitor = Monitor::new(Config {
+                dhcp_lease_dir: lease_dir.display().to_string(),
+                scripts_dir: scripts_dir.display().to_string(),
+                route_lookup: RouteLookup::Lease,
+                no_trigger_on_rediscovery,
+                trigger_on_renew,
+                ..Default::default()
+            });
+            select_interfaces(&mut monitor, "lo*");
+            assert_eq!(monitor.run_once(), 2);
+
+            select_interfaces(&mut monitor, "em*");
+            assert_eq!(monitor.run_once(), 0);
+            assert!(!monitor.lease_params.contains_key(iface_name));
+            // Nor re-run on SIGUSR1
+            monitor.retrigger.store(true, Ordering::Relaxed);
+            assert_eq!(monitor.run_once(), 0);
+            assert_eq!(
+                monitor.parked_lease_params.contains_key(iface_name),
+                no_trigger_on_rediscovery
+            );
+
+            select_interfaces(&mut monitor, "lo*");
+            let expected = if no_trigger_on_rediscovery { 0 } else { 2 };
+            assert_eq!(monitor.run_once(), expected);
+            assert!(monitor.lease_params.contains_key(iface_name));
+            assert!(monitor.parked_lease_params.is_empty());
+        }
+
+        // A lease that changed meanwhile is triggered
+        let mut monitor = Monitor::new(Config {
+            dhcp_lease_dir: lease_dir.display().to_string(),
+            scripts_dir: scripts_dir.display().to_string(),
+            route_lookup: RouteLookup::Lease,
+            no_trigger_on_rediscovery: true,
+            ..Default::default()
+        });
+        select_interfaces(&mut monitor, "lo*");
+        assert_eq!(monitor.run_once(), 2);
+        select_interfaces(&mut monitor, "em*");
+        assert_eq!(monitor.run_once(), 0);
+        fs::write(&lease_path, "ip: 192.0.2.11\n").unwrap();
+        select_interfaces(&mut monitor, "lo*");
+        assert_eq!(monitor.run_once(), 2);
+        assert_eq!(
+            monitor.lease_params[iface_name].ip_addr,
+            Some(Ipv4Addr::new(192, 0, 2, 11))
+        );
+    }
+
+    #[test]
+    fn retrigger_postponed_by_the_pre_hook_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let lease_dir = dir.path().join("leases");
+        let scripts_dir = dir.path().join("scripts");
+        fs::create_dir(&lease_dir).unwrap();
+        fs::create_dir(&scripts_dir).unwrap();
+        let fail_path = dir.path().join("fail");
+        let pre_hook_path = scripts_dir.join("pre");
+        for (path, script) in [
+            (
+                scripts_dir.join("lease_trigger_em0"),
+                String::from("#!/bin/sh\n"),
+            ),
+            (
+                pre_hook_path.clone(),
+                format!("#!/bin/sh\n! test -e '{}'\n", fail_path.display()),
+            ),
+        ] {
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        fs::write(lease_dir.join("em0"), "ip: 192.0.2.10\n").unwrap();
+
+        let mut monitor = Monitor::new(Config {
+            dhcp_lease_dir: lease_dir.display().to_string(),
+            scripts_dir: scripts_dir.display().to_string(),
+            route_lookup: RouteLookup::Lease,
+            interfaces: vec![InterfaceConfig::new("em0")],
+            pre_hook: Some(pre_hook_path.display().to_string()),
+            pre_hook_required: true,
+            ..Default::default()
+        });
+        assert_eq!(monitor.run_once(), 2);
+
+        // SIGUSR1 while the pre-trigger hook fails
+        fs::write(&fail_path, "").unwrap();
+        monitor.retrigger.store(true, Ordering::Relaxed);
+        assert_eq!(monitor.run_once(), 1);
+        assert!(monitor.postponed_changes.contains_key("em0"));
+
+        fs::remove_file(&fail_path).unwrap();
+        assert_eq!(monitor.run_once(), 2);
+        assert!(monitor.postponed_changes.is_empty());
+    }
+}
+
+// EOF
@@ -0,0 +1,121 @@
+//! Lease changes published to an MQTT broker (`--mqtt-broker`) as retained
+//! messages, the connection is kept up by a thread of its own
+
+use log::{debug, error, info, warn};
+use rumqttc::{Client, Event, MqttOptions, Outgoing, Packet, QoS};
+use std::io;
+use std::process;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+/// Default MQTT broker port
+const MQTT_DEFAULT_PORT: u16 = 1883;
+
+/// Keep alive interval of the broker connection
+const MQTT_KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// Delay before reconnecting to the broker after a connection error
+const MQTT_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Messages waiting to be published, further ones are dropped
+const MQTT_QUEUE_SIZE: usize = 64;
+
+/// How long the queued messages get to be published on shutdown
+const MQTT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Mqtt {
+    client: Client,
+    thread: thread::JoinHandle<()>,
+    topic_prefix: String,
+}
+
+impl Mqtt {
+    /// Starts the connection to the broker (`host[:port]`)
+    pub fn spawn(
+        broker: &str,
+        topic_prefix: &str,
+        username: Option<&str>,
+        password: Option<&str>,
+    ) -> io::Result<Self> {
+        let (host, port) = match broker.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid MQTT broker port: {}", port),
+                    )
+                })?,
+            ),
+            None => (broker, MQTT_DEFAULT_PORT),
+        };
+
+        let mut options = MqttOptions::new(format!("dhcpleasemon-{}", process::id()), host, port);
+        options.set_keep_alive(MQTT_KEEP_ALIVE);
+        if let Some(username) = username {
+            options.set_credentials(username, password.unwrap_or(""));
+        }
+
+        let (client, mut connection) = Client::new(options, MQTT_QUEUE_SIZE);
+        let broker = broker.to_owned();
+        let thread = thread::Builder::new()
+            .name(String::from("mqtt"))
+            .spawn(move || {
+                // Warned once until connected again
+                let mut warned = false;
+                // Reconnects on the next iteration after an error
+                for notification in connection.iter() {
+                    match notification {
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                            info!("Connected to MQTT broker {}", broker);
+                            warned = false;
+                        }
+                        Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                        Ok(event) => debug!("MQTT: {:?}", event),
+                        Err(e) => {
+                            if !warned {
+                                warn!("MQTT connection to {} failed: {}", broker, e);
+                                warned = true;
+                            }
+                            sleep(MQTT_RECONNECT_DELAY);
+                        }
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            client,
+            thread,
+            topic_prefix: topic_prefix.to_owned(),
+        })
+    }
+
+    /// Queues a retained message to `<prefix>/<iface>/<family>`
+    pub fn publish(&self, iface_name: &str, family: &str, payload: String) {
+        let topic = format!("{}/{}/{}", self.topic_prefix, iface_name, family);
+        if let Err(e) = self
+            .client
+            .try_publish(&topic, QoS::AtLeastOnce, true, payload)
+        {
+            error!("Failed to publish to MQTT topic {}: {}", topic, e);
+        }
+    }
+
+    /// Disconnects once the queued messages are published, giving up after
+    /// a while if the broker is unreachable
+    pub fn wait(self) {
+        if self.client.disconnect().is_err() {
+            return;
+        }
+        let deadline = Instant::now() + MQTT_SHUTDOWN_TIMEOUT;
+        while !self.thread.is_finished() {
+            if Instant::now() >= deadline {
+                warn!("MQTT messages not published before shutdown");
+                return;
+            }
+            sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+// EOF
@@ -0,0 +1,187 @@
+//! PID file, locked (flock) for as long as the daemon runs so that a second
+//! instance refuses to start
+
+use log::{error, info, warn};
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process;
+
+/// Default PID file location: /run on Linux (/var/run is a symlink to it on
+/// most distributions), /var/run elsewhere
+pub fn default_path() -> &'static str {
+    if cfg!(target_os = "linux") {
+        "/run/dhcpleasemon.pid"
+    } else {
+        "/var/run/dhcpleasemon.pid"
+    }
+}
+
+#[derive(Debug)]
+pub enum PidFileError {
+    /// PID file could not be opened or written
+    Io { path: String, source: io::Error },
+    /// Another instance holds the lock
+    Locked { path: String, pid: Option<i32> },
+    /// Directory of the PID file does not exist
+    NoDirectory { path: String, dir: String },
+    /// Directory of the PID file is not writable
+    ReadOnlyDirectory { path: String, dir: String },
+}
+
+impl fmt::Display for PidFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PidFileError::Io { path, source } => {
+                write!(f, "Failed to write PID file {}: {}", path, source)
+            }
+            PidFileError::Locked {
+                path,
+                pid: Some(pid),
+            } => {
+                write!(f, "Already running (pid {}, PID file {})", pid, path)
+            }
+            PidFileError::Locked { path, pid: None } => {
+                write!(f, "Already running (PID file {} is locked)", path)
+            }
+            PidFileError::NoDirectory { path, dir } => {
+                write!(f, "Directory {} of PID file {} does not exist", dir, path)
+            }
+            PidFileError::ReadOnlyDirectory { path, dir } => {
+                write!(f, "Directory {} of PID file {} is not writable", dir, path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PidFileError {}
+
+pub struct PidFile {
+    path: String,
+    /// Holds the lock, the forked daemon inherits it
+    file: File,
+}
+
+impl PidFile {
+    /// Opens and locks the PID file, failing if another instance holds it.
+    /// A PID file left behind by an instance that is gone is reused.
+    pub fn lock(path: &str) -> Result<Self, PidFileError> {
+        let io_error = |source| PidFileError::Io {
+            path: path.to_owned(),
+            source,
+        };
+
+        // Otherwise only a bare "No such file or directory" is reported
+        if let Some(dir) = Path::new(path).parent() {
+            if !dir.as_os_str().is_empty() && !dir.is_dir() {
+                return Err(PidFileError::NoDirectory {
+                    path: path.to_owned(),
+                    dir: dir.display().to_string(),
+                });
+            }
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(io_error)?;
+
+        let mut content = String::new();
+        let pid = file
+            .read_to_string(&mut content)
+            .ok()
+            .and_then(|_| content.trim().parse::<i32>().ok());
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::WouldBlock {
+                return Err(PidFileError::Locked {
+                    path: path.to_owned(),
+                    pid,
+                });
+            }
+            return Err(io_error(e));
+        }
+
+        if let Some(pid) = pid {
+            info!("Removing stale PID file {} (pid {})", path, pid);
+        }
+        file.set_len(0).map_err(io_error)?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+        })
+    }
+
+    /// Checks that the PID file can be created: its directory exists and is
+    /// writable (`--check`)
+    pub fn check_dir(path: &str) -> Result<(), PidFileError> {
+        let dir = match Path::new(path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if !dir.is_dir() {
+            return Err(PidFileError::NoDirectory {
+                path: path.to_owned(),
+                dir: dir.display().to_string(),
+            });
+        }
+
+        let writable = CString::new(dir.as_os_str().as_bytes())
+            .map(|dir| unsafe { libc::access(dir.as_ptr(), libc::W_OK) } == 0)
+            .unwrap_or(false);
+        if !writable {
+            return Err(PidFileError::ReadOnlyDirectory {
+                path: path.to_owned(),
+                dir: dir.display().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes the PID of this process, call it after daemonizing
+    pub fn write_pid(&mut self) -> Result<(), PidFileError> {
+        self.file
+            .set_len(0)
+            .and_then(|_| self.file.rewind())
+            .and_then(|_| writeln!(self.file, "{}", process::id()))
+            .map_err(|source| PidFileError::Io {
+                path: self.path.to_owned(),
+                source,
+            })
+    }
+
+    /// Hands the PID file over to the user the daemon is about to switch
+    /// to, so that it can still remove it (e.g. in a sticky directory)
+    pub fn chown(&self, uid: Option<u32>, gid: Option<u32>) -> Result<(), PidFileError> {
+        std::os::unix::fs::fchown(&self.file, uid, gid).map_err(|source| PidFileError::Io {
+            path: self.path.to_owned(),
+            source,
+        })
+    }
+
+    /// Removes the PID file, releasing the lock. Without the permission to
+    /// remove it (after dropping privileges) it is emptied instead: a PID
+    /// file that is not locked is reused by the next instance.
+    pub fn remove(self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            match self.file.set_len(0) {
+                Ok(_) => warn!(
+                    "Failed to remove PID file {}: {}, emptied it instead",
+                    self.path, e
+                ),
+                Err(_) => error!("Failed to remove PID file {}: {}", self.path, e),
+            }
+        }
+    }
+}
+
+// EOF
@@ -0,0 +1,611 @@
+//! Default route lookup: native (sysctl) on OpenBSD, netstat elsewhere
+
+use crate::monitor::MonitorError;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How the routing table is obtained
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteLookup {
+    /// Read the routing table from the kernel (OpenBSD only)
+    Sysctl,
+    /// Parse the output of `netstat -rn`
+    Netstat,
+    /// Take the router offered in the lease (IPv4 only, IPv6 routes are
+    /// read from the kernel/netstat as by default)
+    Lease,
+}
+
+impl Default for RouteLookup {
+    fn default() -> Self {
+        if cfg!(target_os = "openbsd") {
+            RouteLookup::Sysctl
+        } else {
+            RouteLookup::Netstat
+        }
+    }
+}
+
+/// Default routes from a single routing table snapshot
+#[derive(Default)]
+pub struct RouteTable {
+    /// Default route per interface
+    pub default_routes: HashMap<String, String>,
+    /// First default route, regardless of the interface
+    pub global_default_route: Option<String>,
+}
+
+impl RouteTable {
+    /// Records a default route (the first one per interface wins)
+    pub fn add_default_route(&mut self, route_iface: &str, route_ip: &str) {
+        self.default_routes
+            .entry(route_iface.to_string())
+            .or_insert(route_ip.to_string());
+        if self.global_default_route.is_none() {
+            self.global_default_route = Some(route_ip.to_string());
+        }
+    }
+}
+
+/// Source of the routing table
+pub trait RouteProvider {
+    /// Gets the default routes for proto ("inet" or "inet6")
+    fn route_table(&self, proto: &str) -> Result<RouteTable, MonitorError>;
+}
+
+/// Location of the netstat binary (`--netstat-path`); a bare name is looked
+/// up in PATH
+pub fn default_netstat_path() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "/usr/sbin/netstat"
+    } else if cfg!(any(target_os = "openbsd", target_os = "freebsd")) {
+        "/usr/bin/netstat"
+    } else {
+        "netstat"
+    }
+}
+
+/// Does the lookup run netstat (the native lookup is OpenBSD only)?
+pub fn uses_netstat(route_lookup: RouteLookup) -> bool {
+    route_lookup == RouteLookup::Netstat || !cfg!(target_os = "openbsd")
+}
+
+/// Creates the route provider for the given lookup method
+pub fn provider(route_lookup: RouteLookup, netstat_path: &str) -> Box<dyn RouteProvider> {
+    match route_lookup {
+        #[cfg(target_os = "openbsd")]
+        RouteLookup::Sysctl => Box::new(SysctlRouteProvider),
+        #[cfg(not(target_os = "openbsd"))]
+        RouteLookup::Sysctl => {
+            warn!("Native route lookup is not supported on this platform, using netstat");
+            Box::new(NetstatRouteProvider::new(netstat_path))
+        }
+        RouteLookup::Netstat => Box::new(NetstatRouteProvider::new(netstat_path)),
+        RouteLookup::Lease => provider(RouteLookup::default(), netstat_path),
+    }
+}
+
+/// Parses the output of `netstat -rn -f <proto>`
+pub struct NetstatRouteProvider {
+    netstat_path: String,
+    /// Did the last run fail? Only the first failure is logged as an error
+    failing: AtomicBool,
+}
+
+/// Positions of the netstat columns that are used, they differ between
+/// systems (and netstat versions)
+struct NetstatColumns {
+    gateway: usize,
+    iface: usize,
+}
+
+impl NetstatColumns {
+    /// Finds the columns in a header line (`Destination Gateway ...`)
+    fn from_header(cols: &[&str]) -> Option<Self> {
+        let position = |names: &[&str]| cols.iter().position(|col| names.contains(col));
+        Some(Self {
+            gateway: position(&["Gateway"])?,
+            iface: position(&["Iface", "Netif", "Interface"])?,
+        })
+    }
+}
+
+impl Default for NetstatColumns {
+    /// OpenBSD: Destination Gateway Flags Refs Use Mtu Prio Iface
+    fn default() -> Self {
+        Self {
+            gateway: 1,
+            iface: 7,
+        }
+    }
+}
+
+impl NetstatRouteProvider {
+    pub fn new(netstat_path: &str) -> Self {
+        Self {
+            netstat_path: netstat_path.to_owned(),
+            failing: AtomicBool::new(false),
+        }
+    }
+
+    /// Logs a failed run: as an error the first time, then (until netstat
+    /// succeeds again) at debug level, as it fails on every scan
+    fn log_failure(&self, message: String) {
+        if self.failing.swap(true, Ordering::Relaxed) {
+            debug!("{}", message);
+        } else {
+            error!("{}", message);
+        }
+    }
+
+    fn parse(netstat_output: &str) -> RouteTable {
+        let mut route_table = RouteTable::default();
+        let mut columns = NetstatColumns::default();
+
+        for line in netstat_output.lines() {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            if cols.first() == Some(&"Destination") {
+                match NetstatColumns::from_header(&cols) {
+                    Some(header_columns) => columns = header_columns,
+                    None => warn!("Unknown netstat header: {}", line.trim()),
+                }
+                continue;
+            }
+
+            if cols.first() != Some(&"default") {
+                continue;
+            }
+            // Columns after the interface (e.g. Expire) may be empty
+            match (cols.get(columns.gateway), cols.get(columns.iface)) {
+                // Interface-scoped routes without a gateway (link#<n> on
+                // macOS and FreeBSD) are skipped
+                (Some(route_ip), Some(_)) if !Self::is_gateway_addr(route_ip) => {
+                    debug!("Default route without a gateway: {}", line.trim())
+                }
+                (Some(route_ip), Some(route_iface)) => {
+                    route_table.add_default_route(route_iface, route_ip)
+                }
+                _ => debug!("Unexpected netstat default route: {}", line.trim()),
+            }
+        }
+
+        route_table
+    }
+
+    /// Is the gateway column an address (possibly with a %scope)?
+    fn is_gateway_addr(gateway: &str) -> bool {
+        let addr = gateway.split('%').next().unwrap_or_default();
+        addr.parse::<IpAddr>().is_ok()
+    }
+}
+
+impl RouteProvider for NetstatRouteProvider {
+    fn route_table(&self, proto: &str) -> Result<RouteTable, MonitorError> {
+        let output = match Command::new(&self.netstat_path)
+            .arg("-rn")
+            .arg("-f")
+            .arg(proto)
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) => {
+                // E.g. missing inside the chroot, see --netstat-path
+                self.log_failure(format!(
+                    "Failed to run {} ({}), no default routes: {}",
+                    self.netstat_path, proto, e
+                ));
+                return Ok(RouteTable::default());
+            }
+        };
+
+        if !output.status.success() {
+            self.log_failure(format!(
+                "Failed to obtain routes ({}): {}",
+                proto, output.status
+            ));
+            return Ok(RouteTable::default());
+        }
+        if self.failing.swap(false, Ordering::Relaxed) {
+            info!("{} works again", self.netstat_path);
+        }
+
+        Ok(Self::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// Reads the routing table with sysctl(NET_RT_DUMP)
+#[cfg(target_os = "openbsd")]
+pub struct SysctlRouteProvider;
+
+#[cfg(target_os = "openbsd")]
+impl SysctlRouteProvider {
+    /// Dumps the routing table (main table) for the address family
+    fn dump(af: libc::c_int) -> std::io::Result<Vec<u8>> {
+        let mib = [
+            libc::CTL_NET,
+            libc::PF_ROUTE,
+            0,
+            af,
+            libc::NET_RT_DUMP,
+            0,
+            0,
+        ];
+
+        loop {
+            let mut len: libc::size_t = 0;
+            let ret = unsafe {
+                libc::sysctl(
+                    mib.as_ptr(),
+                    mib.len() as libc::c_uint,
+                    std::ptr::null_mut(),
+                    &mut len,
+                    std::ptr::null_mut(),
+                    0,
+                )
+            };
+            if ret != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            let mut buf = vec![0u8; len];
+            let ret = unsafe {
+                libc::sysctl(
+                    mib.as_ptr(),
+                    mib.len() as libc::c_uint,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    &mut len,
+                    std::ptr::null_mut(),
+                    0,
+                )
+            };
+            if ret == 0 {
+                buf.truncate(len);
+                return Ok(buf);
+            }
+
+            // The table grew in between, try again
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ENOMEM) {
+                return Err(e);
+            }
+        }
+    }
+
+    /// Parses the routing messages (struct rt_msghdr followed by sockaddrs)
+    fn parse(buf: &[u8], af: libc::c_int) -> RouteTable {
+        let mut route_table = RouteTable::default();
+        let read_u16 = |b: &[u8], at: usize| u16::from_ne_bytes([b[at], b[at + 1]]);
+        let read_i32 =
+            |b: &[u8], at: usize| i32::from_ne_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]]);
+
+        let mut offset = 0;
+        while offset + RTM_MIN_LEN <= buf.len() {
+            let msg_len = read_u16(buf, offset) as usize;
+            if msg_len < RTM_MIN_LEN || offset + msg_len > buf.len() {
+                break;
+            }
+            let msg = &buf[offset..offset + msg_len];
+            offset += msg_len;
+
+            if msg[2] as libc::c_int != libc::RTM_VERSION {
+                continue;
+            }
+
+            let hdr_len = read_u16(msg, 4) as usize;
+            let iface_index = read_u16(msg, 6);
+            let addrs = read_i32(msg, 12);
+            let flags = read_i32(msg, 16);
+
+            let route_flags = libc::RTF_UP | libc::RTF_GATEWAY;
+            if flags & route_flags != route_flags {
+                continue;
+            }
+
+            let sockaddrs = Self::split_sockaddrs(msg, hdr_len, addrs);
+            let dst = sockaddrs[libc::RTAX_DST as usize];
+            let gateway = sockaddrs[libc::RTAX_GATEWAY as usize];
+            let netmask = sockaddrs[libc::RTAX_NETMASK as usize];
+
+            let is_default = match dst {
+                Some(dst) => {
+                    Self::is_zero_addr(dst, af) && netmask.is_none_or(|m| Self::is_zero_addr(m, af))
+                }
+                None => false,
+            };
+            if !is_default {
+                continue;
+            }
+
+            let route_iface = match Self::iface_name(iface_index) {
+                Some(route_iface) => route_iface,
+                None => continue,
+            };
+            if let Some(route_ip) = gateway.and_then(|gw| Self::format_addr(gw, &route_iface)) {
+                route_table.add_default_route(&route_iface, &route_ip);
+            }
+        }
+
+        route_table
+    }
+
+    /// Splits the sockaddrs following the header, indexed by RTAX_*
+    fn split_sockaddrs(msg: &[u8], hdr_len: usize, addrs: i32) -> [Option<&[u8]>; RTAX_MAX] {
+        let mut sockaddrs = [None; RTAX_MAX];
+        let mut offset = hdr_len;
+
+        for (i, sockaddr) in sockaddrs.iter_mut().enumerate() {
+            if addrs & (1 << i) == 0 {
+                continue;
+            }
+            if offset >= msg.len() {
+                break;
+            }
+            let sa_len = msg[offset] as usize;
+            let end = (offset + sa_len).min(msg.len());
+            *sockaddr = Some(&msg[offset..end]);
+
+            // sockaddrs are padded to the size of a long
+            let align = std::mem::size_of::<libc::c_long>();
+            offset += if sa_len > 0 {
+                1 + ((sa_len - 1) | (align - 1))
+            } else {
+                align
+            };
+        }
+
+        sockaddrs
+    }
+
+    /// Is the address in the sockaddr unspecified (0.0.0.0, ::)? Short
+    /// netmask sockaddrs only carry the leading non-zero bytes.
+    fn is_zero_addr(sockaddr: &[u8], af: libc::c_int) -> bool {
+        let addr_offset = if af == libc::AF_INET6 { 8 } else { 4 };
+        sockaddr.iter().skip(addr_offset).all(|b| *b == 0)
+    }
+
+    /// Formats the address in a gateway sockaddr like netstat does
+    fn format_addr(sockaddr: &[u8], iface_name: &str) -> Option<String> {
+        match sockaddr.get(1).map(|f| *f as libc::c_int) {
+            Some(libc::AF_INET) => {
+                let octets: [u8; 4] = sockaddr.get(4..8)?.try_into().ok()?;
+                Some(std::net::Ipv4Addr::from(octets).to_string())
+            }
+            Some(libc::AF_INET6) => {
+                let mut octets: [u8; 16] = sockaddr.get(8..24)?.try_into().ok()?;
+                if octets[0] == 0xfe && octets[1] & 0xc0 == 0x80 {
+                    // Link-local, the kernel embeds the scope in the address
+                    octets[2] = 0;
+                    octets[3] = 0;
+                    let addr = std::net::Ipv6Addr::from(octets);
+                    return Some(format!("{}%{}", addr, iface_name));
+                }
+                Some(std::net::Ipv6Addr::from(octets).to_string())
+            }
+            _ => None,
+        }
+    }
+
+    fn iface_name(iface_index: u16) -> Option<String> {
+        let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+        let ret = unsafe { libc::if_indextoname(iface_index.into(), name.as_mut_ptr()) };
+        if ret.is_null() {
+            return None;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+        Some(name.to_string_lossy().into_owned())
+    }
+}
+
+/// Smallest rt_msghdr prefix that holds every field read by the parser
+#[cfg(target_os = "openbsd")]
+const RTM_MIN_LEN: usize = 20;
+
+#[cfg(target_os = "openbsd")]
+const RTAX_MAX: usize = libc::RTAX_MAX as usize;
+
+#[cfg(target_os = "openbsd")]
+impl RouteProvider for SysctlRouteProvider {
+    fn route_table(&self, proto: &str) -> Result<RouteTable, MonitorError> {
+        let af = if proto == "inet6" {
+            libc::AF_INET6
+        } else {
+            libc::AF_INET
+        };
+        let buf = Self::dump(af).map_err(|source| MonitorError::RouteLookup {
+            method: "sysctl",
+            source,
+        })?;
+        Ok(Self::parse(&buf, af))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn netstat_parse_takes_default_routes() {
+        let output = "\
+Routing tables
+
+Internet:
+Destination        Gateway            Flags   Refs      Use   Mtu  Prio Iface
+default            192.0.2.1          UGS        5     1234     -     8 em0
+default            198.51.100.1       UGS        0        0     -    12 em1
+127/8              127.0.0.1          UGRS       0        0 32768     8 lo0
+";
+        let route_table = NetstatRouteProvider::parse(output);
+        assert_eq!(route_table.default_routes.len(), 2);
+        assert_eq!(route_table.default_routes["em0"], "192.0.2.1");
+        assert_eq!(route_table.default_routes["em1"], "198.51.100.1");
+        assert_eq!(
+            route_table.global_default_route.as_deref(),
+            Some("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn netstat_parse_without_default_route() {
+        let output = "\
+Destination        Gateway            Flags   Refs      Use   Mtu  Prio Iface
+127/8              127.0.0.1          UGRS       0        0 32768     8 lo0
+";
+        let route_table = NetstatRouteProvider::parse(output);
+        assert!(route_table.default_routes.is_empty());
+        assert!(route_table.global_default_route.is_none());
+    }
+
+    #[test]
+    fn netstat_parse_openbsd_inet6() {
+        let output = "\
+Routing tables
+
+Internet6:
+Destination                        Gateway                        Flags   Refs      Use   Mtu  Prio Iface
+default                            fe80::1%em0                    UGS        0       12     -     8 em0
+::1                                ::1                            UHhl      10       20 32768     1 lo0
+fe80::%em0/64                      fe80::5054:ff:fe12:3456%em0    UCn        1        0     -     4 em0
+";
+        let route_table = NetstatRouteProvider::parse(output);
+        assert_eq!(route_table.default_routes.len(), 1);
+        assert_eq!(route_table.default_routes["em0"], "fe80::1%em0");
+    }
+
+    #[test]
+    fn netstat_parse_freebsd() {
+        let output = "\
+Routing tables
+
+Internet:
+Destination        Gateway            Flags         Netif Expire
+default            192.0.2.1          UGS             em0
+127.0.0.1          link#2             UH              lo0
+192.0.2.0/24       link#1             U               em0
+192.0.2.10         link#1             UHS             lo0
+";
+        let route_table = NetstatRouteProvider::parse(output);
+        assert_eq!(route_table.default_routes.len(), 1);
+        assert_eq!(route_table.default_routes["em0"], "192.0.2.1");
+
+        let output = "\
+Routing tables
+
+Internet6:
+Destination                       Gateway                       Flags         Netif Expire
+::/96                             ::1                           UGRS            lo0
+default                           fe80::1%em0                   UG              em0
+::1                               link#2                        UHS             lo0
+fe80::%em0/64                     link#1                        U               em0
+";
+        let route_table = NetstatRouteProvider::parse(output);
+        assert_eq!(route_table.default_routes.len(), 1);
+        assert_eq!(route_table.default_routes["em0"], "fe80::1%em0");
+    }
+
+    #[test]
+    fn netstat_parse_macos() {
+        let output = "\
+Routing tables
+
+Internet:
+Destination        Gateway            Flags               Netif Expire
+default            192.0.2.1          UGScg                 en0
+127                127.0.0.1          UCS                   lo0
+192.0.2            link#6             UCS                   en0      !
+192.0.2.1/32       link#6             UCS                   en0      !
+";
+        let route_table = NetstatRouteProvider::parse(output);
+        assert_eq!(route_table.default_routes.len(), 1);
+        assert_eq!(route_table.default_routes["en0"], "192.0.2.1");
+
+        let output = "\
+Routing tables
+
+Internet6:
+Destination                             Gateway                                 Flags               Netif Expire
+default                                 fe80::1%en0                             UGcg                  en0
+default                                 fe80::%utun0                            UGcIg               utun0
+::1                                     ::1                                     UHL                   lo0
+";
+        let route_table = NetstatRouteProvider::parse(output);
+        assert_eq!(route_table.default_routes.len(), 2);
+        assert_eq!(route_table.default_routes["en0"], "fe80::1%en0");
+        assert_eq!(route_table.default_routes["utun0"], "fe80::%utun0");
+        assert_eq!(
+            route_table.global_default_route.as_deref(),
+            Some("fe80::1%en0")
+        );
+    }
+
+    #[test]
+    fn netstat_columns_follow_the_header() {
+        // Older macOS and FreeBSD: Refs and Use before Netif
+        let output = "\
+Destination        Gateway            Flags        Refs      Use   Netif Expire
+default            192.0.2.1          UGSc           25        0     en0
+";
+        let route_table = NetstatRouteProvider::parse(output);
+        assert_eq!(route_table.default_routes["en0"], "192.0.2.1");
+
+        // Interface column named Interface, gateway not second
+        let output = "\
+Destination        Flags    Gateway            Interface
+default            UG       192.0.2.1          eth0
+";
+        let route_table = NetstatRouteProvider::parse(output);
+        assert_eq!(route_table.default_routes["eth0"], "192.0.2.1");
+    }
+
+    #[test]
+    fn netstat_columns_default_to_openbsd() {
+        // No header, or one without the columns: the OpenBSD layout
+        let route = "default            192.0.2.1          UGS        5     1234     -     8 em0\n";
+        let route_table = NetstatRouteProvider::parse(route);
+        assert_eq!(route_table.default_routes["em0"], "192.0.2.1");
+
+        let output = format!("Destination        Via                Flags\n{}", route);
+        let route_table = NetstatRouteProvider::parse(&output);
+        assert_eq!(route_table.default_routes["em0"], "192.0.2.1");
+    }
+
+    #[test]
+    fn netstat_skips_default_routes_without_gateway() {
+        let output = "\
+Destination        Gateway            Flags               Netif Expire
+default            link#14            UCSIg           bridge100      !
+default            192.0.2.1          UGScg                 en0
+default            fe80::1%en0        UGcg                  en1
+";
+        let route_table = NetstatRouteProvider::parse(output);
+        assert!(!route_table.default_routes.contains_key("bridge100"));
+        assert_eq!(route_table.default_routes["en0"], "192.0.2.1");
+        assert_eq!(route_table.default_routes["en1"], "fe80::1%en0");
+        assert_eq!(
+            route_table.global_default_route.as_deref(),
+            Some("192.0.2.1")
+        );
+        assert!(!NetstatRouteProvider::is_gateway_addr("link#14"));
+        assert!(!NetstatRouteProvider::is_gateway_addr("00:11:22:33:44:55"));
+    }
+
+    #[test]
+    fn netstat_failure_is_remembered_until_it_runs() {
+        let mut provider = NetstatRouteProvider::new("/nonexistent/netstat");
+        for _ in 0..2 {
+            let route_table = provider.route_table("inet").unwrap();
+            assert!(route_table.default_routes.is_empty());
+            assert!(provider.failing.load(Ordering::Relaxed));
+        }
+
+        provider.netstat_path = String::from("true");
+        provider.route_table("inet").unwrap();
+        assert!(!provider.failing.load(Ordering::Relaxed));
+    }
+}
+
+// EOF
@@ -0,0 +1,48 @@
+//! pledge(2)/unveil(2) wrappers; no-ops on platforms other than OpenBSD
+
+use std::io;
+
+#[cfg(target_os = "openbsd")]
+use std::ffi::CString;
+
+/// Makes only the given paths visible, with the given permissions
+/// ("r", "rx", ...), and locks the unveil list
+#[cfg(target_os = "openbsd")]
+pub fn unveil(paths: &[(&str, &str)]) -> io::Result<()> {
+    for (path, permissions) in paths {
+        let c_path = CString::new(*path)?;
+        let c_permissions = CString::new(*permissions)?;
+        if unsafe { libc::unveil(c_path.as_ptr(), c_permissions.as_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    if unsafe { libc::unveil(std::ptr::null(), std::ptr::null()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Restricts the process to the given promises; executed programs are not restricted
+#[cfg(target_os = "openbsd")]
+pub fn pledge(promises: &str) -> io::Result<()> {
+    let c_promises = CString::new(promises)?;
+    if unsafe { libc::pledge(c_promises.as_ptr(), std::ptr::null()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "openbsd"))]
+pub fn unveil(_paths: &[(&str, &str)]) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "openbsd"))]
+pub fn pledge(_promises: &str) -> io::Result<()> {
+    Ok(())
+}
+
+// EOF
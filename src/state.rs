@@ -0,0 +1,69 @@
+//! Last seen lease state, persisted across restarts (`--state-file`)
+
+use crate::lease::{Lease6Params, LeaseParams};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+/// Lease params per interface, as loaded from the state file
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct State {
+    pub lease_params: HashMap<String, LeaseParams>,
+    pub lease6_params: HashMap<String, Lease6Params>,
+}
+
+/// Lease params per interface, as saved to the state file
+#[derive(Serialize)]
+struct SavedState<'a> {
+    lease_params: &'a HashMap<String, LeaseParams>,
+    lease6_params: &'a HashMap<String, Lease6Params>,
+}
+
+/// Loads the state file; a missing or corrupt one gives an empty state
+pub fn load(path: &str) -> State {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return State::default(),
+        Err(e) => {
+            warn!("Failed to read state file {}: {}", path, e);
+            return State::default();
+        }
+    };
+
+    serde_json::from_str(&content).unwrap_or_else(|e| {
+        warn!("Ignoring corrupt state file {}: {}", path, e);
+        State::default()
+    })
+}
+
+/// Saves the state file, replacing the previous one atomically
+pub fn save(
+    path: &str,
+    lease_params: &HashMap<String, LeaseParams>,
+    lease6_params: &HashMap<String, Lease6Params>,
+) -> io::Result<()> {
+    let state = SavedState {
+        lease_params,
+        lease6_params,
+    };
+    let content = serde_json::to_string_pretty(&state)?;
+    write_atomic(path, &content)
+}
+
+/// Writes the file through a temporary one, so that readers never see a
+/// partially written file
+pub fn write_atomic(path: &str, content: &str) -> io::Result<()> {
+    let tmp_path = tmp_path(path);
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Temporary file the state is written to before it replaces the state file
+pub fn tmp_path(path: &str) -> String {
+    format!("{}.tmp", path)
+}
+
+// EOF
@@ -0,0 +1,64 @@
+//! Status for other tools: the current lease of every interface, written to
+//! the status file (`--status-file`) after the scans that changed any (or
+//! whether an interface's trigger failed)
+
+use crate::lease::{Lease6Params, LeaseParams};
+use crate::state;
+use crate::unix_time;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+
+#[derive(Serialize)]
+struct Status<'a> {
+    /// When the file was written (seconds since the epoch)
+    updated: u64,
+    interfaces: BTreeMap<&'a str, InterfaceStatus<'a>>,
+}
+
+#[derive(Serialize, Default)]
+struct InterfaceStatus<'a> {
+    lease: Option<&'a LeaseParams>,
+    lease6: Option<&'a Lease6Params>,
+    /// Time of the last lease change seen (seconds since the epoch)
+    last_change: Option<u64>,
+    /// The last trigger failed, after its retries (until one succeeds)
+    failed: bool,
+}
+
+/// The current lease of every interface, as written to the status file and
+/// sent over the control socket
+pub fn snapshot(
+    lease_params: &HashMap<String, LeaseParams>,
+    lease6_params: &HashMap<String, Lease6Params>,
+    last_change: &HashMap<String, u64>,
+    failed_interfaces: &BTreeSet<String>,
+) -> serde_json::Value {
+    let mut interfaces: BTreeMap<&str, InterfaceStatus> = BTreeMap::new();
+    for (iface_name, params) in lease_params {
+        interfaces.entry(iface_name).or_default().lease = Some(params);
+    }
+    for (iface_name, params) in lease6_params {
+        interfaces.entry(iface_name).or_default().lease6 = Some(params);
+    }
+    for (iface_name, timestamp) in last_change {
+        interfaces.entry(iface_name).or_default().last_change = Some(*timestamp);
+    }
+    for iface_name in failed_interfaces {
+        interfaces.entry(iface_name).or_default().failed = true;
+    }
+
+    let status = Status {
+        updated: unix_time(),
+        interfaces,
+    };
+    serde_json::to_value(status).unwrap_or_default()
+}
+
+/// Writes the status file, replacing the previous one atomically
+pub fn save(path: &str, status: &serde_json::Value) -> io::Result<()> {
+    let content = serde_json::to_string_pretty(status)?;
+    state::write_atomic(path, &content)
+}
+
+// EOF
@@ -0,0 +1,103 @@
+//! systemd service notifications (`Type=notify`, `WatchdogSec=`) and journal
+//! entries; no-ops on platforms other than Linux and when not started by
+//! systemd
+
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::unix::net::UnixDatagram;
+#[cfg(target_os = "linux")]
+use std::sync::OnceLock;
+
+/// Socket of the journal's native protocol
+#[cfg(target_os = "linux")]
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+#[cfg(target_os = "linux")]
+static JOURNAL: OnceLock<UnixDatagram> = OnceLock::new();
+
+/// Tells systemd that the daemon is up and monitoring
+#[cfg(target_os = "linux")]
+pub fn notify_ready() {
+    notify(&[sd_notify::NotifyState::Ready]);
+}
+
+/// Pings the watchdog, if systemd has it enabled for the service
+#[cfg(target_os = "linux")]
+pub fn notify_watchdog() {
+    if sd_notify::watchdog_enabled().is_some() {
+        notify(&[sd_notify::NotifyState::Watchdog]);
+    }
+}
+
+/// Tells systemd that the daemon is shutting down
+#[cfg(target_os = "linux")]
+pub fn notify_stopping() {
+    notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+#[cfg(target_os = "linux")]
+fn notify(state: &[sd_notify::NotifyState]) {
+    if let Err(e) = sd_notify::notify(state) {
+        log::warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// Connects to the journal; needs to happen while its socket is reachable
+#[cfg(target_os = "linux")]
+pub fn open_journal() -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(JOURNAL_SOCKET)?;
+    let _ = JOURNAL.set(socket);
+    Ok(())
+}
+
+/// Sends an entry with the given fields (`MESSAGE`, `PRIORITY`, ...) to the
+/// journal
+#[cfg(target_os = "linux")]
+pub fn journal_send(fields: &[(&str, &str)]) -> io::Result<()> {
+    let socket = JOURNAL
+        .get()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "journal not connected"))?;
+
+    let mut entry = Vec::new();
+    for (name, value) in fields {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            // Multi-line values are length-prefixed
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+    socket.send(&entry).map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify_ready() {}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify_watchdog() {}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify_stopping() {}
+
+#[cfg(not(target_os = "linux"))]
+pub fn open_journal() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the journal is only available on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn journal_send(_fields: &[(&str, &str)]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the journal is only available on Linux",
+    ))
+}
+
+// EOF
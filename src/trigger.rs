@@ -0,0 +1,498 @@
+//! Trigger script runs, off the main loop: every interface gets a worker
+//! thread that runs its trigger scripts one after another
+
+use crate::metrics::Metrics;
+use crate::monitor::MonitorError;
+use log::{debug, error, log, warn, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+/// Trigger script output longer than this is truncated in the logs
+const SCRIPT_OUTPUT_LOG_LIMIT: usize = 8192;
+
+/// How long a timed out script gets to exit after SIGTERM, before SIGKILL
+const SCRIPT_KILL_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// How often a running script is checked for completion
+const SCRIPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How the lease values are passed to the trigger scripts
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ArgsMode {
+    /// Environment variables only
+    #[default]
+    Env,
+    /// Positional arguments only
+    Args,
+    /// Both environment variables and positional arguments
+    Both,
+}
+
+impl ArgsMode {
+    pub fn env(self) -> bool {
+        self != ArgsMode::Args
+    }
+
+    pub fn args(self) -> bool {
+        self != ArgsMode::Env
+    }
+}
+
+/// Environment of a trigger script run
+#[derive(Serialize, Debug, Default)]
+#[serde(transparent)]
+pub struct ScriptEnv {
+    vars: BTreeMap<String, String>,
+}
+
+impl ScriptEnv {
+    pub fn env(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.vars.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn envs<K: Into<String>, V: Into<String>>(
+        &mut self,
+        vars: impl IntoIterator<Item = (K, V)>,
+    ) -> &mut Self {
+        for (key, value) in vars {
+            self.env(key, value);
+        }
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.vars.iter()
+    }
+
+    /// Adds the other environment's variables that are not set yet
+    pub fn merge(&mut self, other: ScriptEnv) {
+        for (key, value) in other.vars {
+            self.vars.entry(key).or_insert(value);
+        }
+    }
+
+    /// Renames the `DHCP_`/`DHCP6_` variables to the given prefix
+    /// (`--env-prefix`)
+    pub fn rename_prefix(&mut self, prefix: &str) {
+        let vars = std::mem::take(&mut self.vars);
+        self.vars = vars
+            .into_iter()
+            .map(|(name, value)| (env_name(prefix, &name), value))
+            .collect();
+    }
+}
+
+/// Name of a `DHCP_` (or `DHCP6_`) variable with the prefix (`--env-prefix`)
+/// in place of `DHCP_`, the IPv6 variables get a `6` appended to the prefix
+/// (`LEASE_IFACE`, `LEASE6_IFACE`); other names are kept
+pub fn env_name(prefix: &str, name: &str) -> String {
+    if let Some(rest) = name.strip_prefix("DHCP_") {
+        return format!("{}{}", prefix, rest);
+    }
+    if let Some(rest) = name.strip_prefix("DHCP6_") {
+        return match prefix.strip_suffix('_') {
+            Some(stem) => format!("{}6_{}", stem, rest),
+            None => format!("{}6{}", prefix, rest),
+        };
+    }
+    name.to_owned()
+}
+
+/// A trigger run: the scripts run one after another with the same environment
+pub struct TriggerJob {
+    pub iface_name: String,
+    /// `inet`, `inet6` or `dual` (`--unified-trigger`), for the metrics
+    pub family: &'static str,
+    pub script_paths: Vec<String>,
+    pub env: ScriptEnv,
+    /// Positional arguments (`--args-mode`)
+    pub args: Vec<String>,
+    /// Written to the scripts' stdin (`--json-stdin`)
+    pub stdin: Option<String>,
+    /// Script timeout in seconds, 0 to wait forever
+    pub timeout: u64,
+    /// Skip the remaining scripts once one fails
+    pub stop_on_error: bool,
+    /// How many times a failing script is run again
+    pub retry_count: u32,
+    /// Delay before the first retry, doubled for every further one
+    pub retry_delay: Duration,
+    /// Called with the result once the scripts ran
+    pub done: Option<Box<dyn FnOnce(bool) + Send>>,
+}
+
+impl TriggerJob {
+    /// Runs the scripts, returns whether they all succeeded
+    fn run(mut self, metrics: &Metrics) -> bool {
+        let mut success = true;
+        for (i, script_path) in self.script_paths.iter().enumerate() {
+            if self.run_script_retrying(script_path, metrics) {
+                continue;
+            }
+            success = false;
+
+            let skipped = self.script_paths.len() - i - 1;
+            if self.stop_on_error && skipped > 0 {
+                warn!(
+                    "Skipping {} remaining trigger script(s) after failure (iface: {})",
+                    skipped, &self.iface_name,
+                );
+                break;
+            }
+        }
+        if let Some(done) = self.done.take() {
+            done(success);
+        }
+        success
+    }
+
+    /// Runs one script, again after a growing delay while it fails (up to
+    /// the retry count), returns whether it succeeded in the end
+    fn run_script_retrying(&self, script_path: &str, metrics: &Metrics) -> bool {
+        let mut delay = self.retry_delay;
+        for retry in 1..=self.retry_count {
+            if self.run_script(script_path, metrics) {
+                return true;
+            }
+            warn!(
+                "Retrying trigger script in {} (retry {}/{}, iface: {}, path: {})",
+                humantime::format_duration(delay),
+                retry,
+                self.retry_count,
+                self.iface_name,
+                script_path,
+            );
+            sleep(delay);
+            delay = delay.saturating_mul(2);
+        }
+
+        let success = self.run_script(script_path, metrics);
+        if !success && self.retry_count > 0 {
+            error!(
+                "Trigger script still failing after {} retries, giving up (iface: {}, path: {})",
+                self.retry_count, self.iface_name, script_path,
+            );
+        }
+        success
+    }
+
+    /// Runs one script, returns whether it succeeded; its duration goes to
+    /// the metrics
+    fn run_script(&self, script_path: &str, metrics: &Metrics) -> bool {
+        debug!("Running trigger script: {}", script_path);
+
+        let mut command = Command::new(script_path);
+        command.envs(self.env.iter()).args(&self.args);
+
+        let started = Instant::now();
+        let output = output_with_timeout(
+            &mut command,
+            self.stdin.as_deref(),
+            Duration::from_secs(self.timeout),
+        );
+        // A script that failed to start has no duration
+        if output.is_ok() {
+            metrics.trigger_duration(self.family, started.elapsed());
+        }
+        let output = output
+            .map_err(|source| MonitorError::Script {
+                path: script_path.to_owned(),
+                source,
+            })
+            .and_then(|output| {
+                output.ok_or_else(|| MonitorError::ScriptTimeout {
+                    path: script_path.to_owned(),
+                    timeout: self.timeout,
+                })
+            });
+
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                error!("{} (iface: {})", e, self.iface_name);
+                return false;
+            }
+        };
+
+        if !output.status.success() {
+            warn!(
+                "Trigger script execution was unsuccessful: {} (iface: {}, path: {})",
+                output.status, &self.iface_name, script_path,
+            );
+        }
+        self.log_output(script_path, &output);
+        output.status.success()
+    }
+
+    /// Logs what the script printed: at debug level if it succeeded, as a
+    /// warning otherwise
+    fn log_output(&self, script_path: &str, output: &Output) {
+        let level = if output.status.success() {
+            Level::Debug
+        } else {
+            Level::Warn
+        };
+
+        for (stream, data) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            if data.is_empty() {
+                continue;
+            }
+            let text = String::from_utf8_lossy(&data[..data.len().min(SCRIPT_OUTPUT_LOG_LIMIT)]);
+            let truncated = if data.len() > SCRIPT_OUTPUT_LOG_LIMIT {
+                " [truncated]"
+            } else {
+                ""
+            };
+            log!(
+                level,
+                "Trigger script {} (iface: {}, path: {}): {}{}",
+                stream,
+                self.iface_name,
+                script_path,
+                text.trim_end(),
+                truncated,
+            );
+        }
+    }
+}
+
+struct TriggerWorker {
+    sender: mpsc::Sender<TriggerJob>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl TriggerWorker {
+    fn spawn(
+        iface_name: &str,
+        failed: Arc<AtomicUsize>,
+        metrics: Arc<Metrics>,
+    ) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::channel::<TriggerJob>();
+        let thread = thread::Builder::new()
+            .name(format!("trigger-{}", iface_name))
+            .spawn(move || {
+                for job in receiver {
+                    let iface_name = job.iface_name.to_owned();
+                    let success = job.run(&metrics);
+                    if !success {
+                        failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    metrics.trigger_completed(&iface_name, success);
+                }
+            })?;
+        Ok(Self { sender, thread })
+    }
+}
+
+/// Trigger workers of the monitored interfaces, started on first use. The
+/// scripts of an interface run in the order they were queued, the scripts
+/// of different interfaces run concurrently.
+#[derive(Default)]
+pub struct TriggerWorkers {
+    workers: HashMap<String, TriggerWorker>,
+    /// Number of script runs that failed
+    failed: Arc<AtomicUsize>,
+    metrics: Arc<Metrics>,
+}
+
+impl TriggerWorkers {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            metrics,
+            ..Default::default()
+        }
+    }
+
+    /// Queues a trigger script run on the interface's worker
+    pub fn queue(&mut self, job: TriggerJob) {
+        if !self.workers.contains_key(&job.iface_name) {
+            match TriggerWorker::spawn(
+                &job.iface_name,
+                Arc::clone(&self.failed),
+                Arc::clone(&self.metrics),
+            ) {
+                Ok(worker) => {
+                    self.workers.insert(job.iface_name.to_owned(), worker);
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to start trigger worker for {}: {}",
+                        job.iface_name, e
+                    );
+                    self.run(job);
+                    return;
+                }
+            }
+        }
+
+        if let Some(worker) = self.workers.get(&job.iface_name) {
+            if let Err(mpsc::SendError(job)) = worker.sender.send(job) {
+                // The worker is gone, run it here rather than lose it
+                self.workers.remove(&job.iface_name);
+                self.run(job);
+            }
+        }
+    }
+
+    /// Runs the script right away, on the calling thread
+    fn run(&self, job: TriggerJob) {
+        let iface_name = job.iface_name.to_owned();
+        let success = job.run(&self.metrics);
+        if !success {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.metrics.trigger_completed(&iface_name, success);
+    }
+
+    /// Number of script runs that failed so far
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Stops the interface's worker once it has run the queued scripts
+    pub fn remove(&mut self, iface_name: &str) {
+        self.workers.remove(iface_name);
+    }
+
+    /// Waits for all queued scripts to complete, stopping the workers
+    pub fn wait(&mut self) {
+        for (iface_name, worker) in self.workers.drain() {
+            drop(worker.sender);
+            if worker.thread.join().is_err() {
+                error!("Trigger worker for {} panicked", iface_name);
+            }
+        }
+    }
+}
+
+/// Runs the command like `Command::output()`, feeding it the stdin data (if
+/// any), but gives up on it after the timeout (zero: no timeout): it is sent
+/// SIGTERM, then SIGKILL, and None is returned
+pub fn output_with_timeout(
+    command: &mut Command,
+    stdin: Option<&str>,
+    timeout: Duration,
+) -> io::Result<Option<Output>> {
+    let mut child = command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Written meanwhile, the script may print before it reads its input; the
+    // pipe is closed once written
+    if let (Some(mut pipe), Some(data)) = (child.stdin.take(), stdin) {
+        let data = data.to_owned();
+        thread::spawn(move || {
+            let _ = pipe.write_all(data.as_bytes());
+        });
+    }
+
+    if timeout.is_zero() {
+        return child.wait_with_output().map(Some);
+    }
+
+    // Drain the pipes meanwhile, a script blocked on a full pipe never exits
+    fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut data = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut data);
+            }
+            data
+        })
+    }
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(Output {
+                status,
+                stdout: stdout.join().unwrap_or_default(),
+                stderr: stderr.join().unwrap_or_default(),
+            }));
+        }
+        sleep(SCRIPT_POLL_INTERVAL);
+    }
+
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let deadline = Instant::now() + SCRIPT_KILL_GRACE_PERIOD;
+    while Instant::now() < deadline {
+        if child.try_wait()?.is_some() {
+            return Ok(None);
+        }
+        sleep(SCRIPT_POLL_INTERVAL);
+    }
+    child.kill()?;
+    child.wait()?;
+
+    // The pipe readers are left behind, anything the script spawned may
+    // still hold the pipes open
+    Ok(None)
+}
+
+/// Path of an interface's trigger script: the prefix followed by the
+/// interface name, in the scripts directory
+pub fn trigger_script_path(scripts_dir: &str, script_prefix: &str, iface_name: &str) -> String {
+    format!("{scripts_dir}/{script_prefix}{iface_name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trigger_script_path_appends_iface() {
+        assert_eq!(
+            trigger_script_path("/etc/dhcpleasemon", "lease_trigger_", "em0"),
+            "/etc/dhcpleasemon/lease_trigger_em0"
+        );
+    }
+
+    #[test]
+    fn env_name_replaces_prefix() {
+        assert_eq!(env_name("DHCP_", "DHCP_IP_ADDR"), "DHCP_IP_ADDR");
+        assert_eq!(env_name("DHCP_", "DHCP6_IP_ADDR"), "DHCP6_IP_ADDR");
+        assert_eq!(env_name("LEASE_", "DHCP_IP_ADDR"), "LEASE_IP_ADDR");
+        assert_eq!(env_name("LEASE_", "DHCP6_IP_ADDR"), "LEASE6_IP_ADDR");
+        assert_eq!(env_name("LEASE", "DHCP6_IP_ADDR"), "LEASE6IP_ADDR");
+        assert_eq!(env_name("LEASE_", "reason"), "reason");
+    }
+
+    #[test]
+    fn rename_prefix_renames_dhcp_vars_only() {
+        let mut env = ScriptEnv::default();
+        env.env("DHCP_IFACE", "em0")
+            .env("DHCP6_IP_PREFIX", "2001:db8::")
+            .env("new_ip_address", "192.0.2.10");
+        env.rename_prefix("LEASE_");
+        let vars: Vec<(&str, &str)> = env
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            vars,
+            vec![
+                ("LEASE6_IP_PREFIX", "2001:db8::"),
+                ("LEASE_IFACE", "em0"),
+                ("new_ip_address", "192.0.2.10"),
+            ]
+        );
+    }
+}
+
+// EOF
@@ -0,0 +1,102 @@
+//! Lease changes POSTed as JSON to a webhook (`--webhook-url`), off the main
+//! loop: a worker thread sends them one after another
+
+use log::{debug, error, warn};
+use std::io;
+use std::sync::mpsc;
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+/// How many times a failed webhook request is retried
+const WEBHOOK_RETRIES: u32 = 3;
+
+/// Delay before the first retry, doubled for every further one
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Timeout of a single webhook request
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Webhook {
+    url: String,
+    sender: mpsc::Sender<String>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Webhook {
+    /// Starts the worker sending to the URL
+    pub fn spawn(url: &str) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::channel::<String>();
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(WEBHOOK_TIMEOUT))
+            .build()
+            .into();
+        let worker_url = url.to_owned();
+        let thread = thread::Builder::new()
+            .name(String::from("webhook"))
+            .spawn(move || {
+                for payload in receiver {
+                    post(&agent, &worker_url, &payload);
+                }
+            })?;
+
+        Ok(Self {
+            url: url.to_owned(),
+            sender,
+            thread,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Queues the payload for sending
+    pub fn send(&self, payload: String) {
+        if self.sender.send(payload).is_err() {
+            error!("Webhook worker is gone, lease change not sent");
+        }
+    }
+
+    /// Waits for the queued payloads to be sent, stopping the worker
+    pub fn wait(self) {
+        drop(self.sender);
+        if self.thread.join().is_err() {
+            error!("Webhook worker panicked");
+        }
+    }
+}
+
+/// POSTs the payload, retrying with backoff on failure
+fn post(agent: &ureq::Agent, url: &str, payload: &str) {
+    let mut delay = WEBHOOK_RETRY_DELAY;
+    for attempt in 0..=WEBHOOK_RETRIES {
+        if attempt > 0 {
+            sleep(delay);
+            delay *= 2;
+        }
+
+        match agent
+            .post(url)
+            .header("Content-Type", "application/json")
+            .send(payload)
+        {
+            Ok(_) => {
+                debug!("Webhook notified: {}", url);
+                return;
+            }
+            Err(e) if attempt < WEBHOOK_RETRIES => {
+                warn!("Webhook request failed, retrying: {} ({})", e, url);
+            }
+            Err(e) => {
+                error!(
+                    "Webhook request failed, giving up after {} attempts: {} ({})",
+                    WEBHOOK_RETRIES + 1,
+                    e,
+                    url,
+                );
+            }
+        }
+    }
+}
+
+// EOF
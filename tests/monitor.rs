@@ -0,0 +1,751 @@
+//! End-to-end scans: lease files in a temporary directory, canned routes and
+//! a trigger script recording the environment it is run with
+
+use dhcpleasemon::config::{Config, InterfaceConfig};
+use dhcpleasemon::lease::LeaseFormat;
+use dhcpleasemon::monitor::{Monitor, MonitorError};
+use dhcpleasemon::route::{RouteProvider, RouteTable};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// Every interface has the default route 192.0.2.1 (and fe80::1 for IPv6)
+struct FakeRouteProvider;
+
+impl RouteProvider for FakeRouteProvider {
+    fn route_table(&self, proto: &str) -> Result<RouteTable, MonitorError> {
+        let mut route_table = RouteTable::default();
+        match proto {
+            "inet6" => route_table.add_default_route("em0", "fe80::1%em0"),
+            _ => route_table.add_default_route("em0", "192.0.2.1"),
+        }
+        Ok(route_table)
+    }
+}
+
+/// A lease directory, a scripts directory with a recording trigger script
+/// for em0, and the file the script appends its environment to
+struct Harness {
+    dir: TempDir,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let harness = Self {
+            dir: tempfile::tempdir().unwrap(),
+        };
+        fs::create_dir(harness.path("leases")).unwrap();
+        fs::create_dir(harness.path("scripts")).unwrap();
+        harness.add_script("lease_trigger_em0");
+        harness
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Adds a trigger script that records its DHCP variables (and `ZONE`,
+    /// an interface's own one), one run per block, the blocks separated by
+    /// an empty line
+    fn add_script(&self, name: &str) {
+        let script_path = self.path("scripts").join(name);
+        fs::write(
+            &script_path,
+            format!(
+                "#!/bin/sh\n{{ echo \"SCRIPT={}\"; env | grep -E '^(DHCP|ZONE=)' | sort; echo; }} >> '{}'\n",
+                name,
+                self.path("runs").display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn config(&self) -> Config {
+        Config {
+            dhcp_lease_dir: self.path("leases").display().to_string(),
+            dhcp6_lease_dir: self.path("leases6").display().to_string(),
+            scripts_dir: self.path("scripts").display().to_string(),
+            interfaces: vec![InterfaceConfig::new("em0")],
+            ..Default::default()
+        }
+    }
+
+    fn monitor(&self, config: Config) -> Monitor {
+        Monitor::with_route_provider(config, Box::new(FakeRouteProvider))
+    }
+
+    /// Replaces the lease file (a new file, as the DHCP clients write them)
+    fn write_lease(&self, dir: &str, name: &str, content: &str) {
+        let lease_dir = self.path(dir);
+        fs::create_dir_all(&lease_dir).unwrap();
+        let tmp_path = lease_dir.join(format!(".{}.tmp", name));
+        fs::write(&tmp_path, content).unwrap();
+        fs::rename(&tmp_path, lease_dir.join(name)).unwrap();
+    }
+
+    fn remove_lease(&self, dir: &str, name: &str) {
+        fs::remove_file(self.path(dir).join(name)).unwrap();
+    }
+
+    /// The trigger script runs since the last call
+    fn take_runs(&self) -> Vec<HashMap<String, String>> {
+        let runs_path = self.path("runs");
+        let Ok(content) = fs::read_to_string(&runs_path) else {
+            return Vec::new();
+        };
+        fs::remove_file(&runs_path).unwrap();
+        content
+            .split("\n\n")
+            .filter(|run| !run.trim().is_empty())
+            .map(|run| {
+                run.lines()
+                    .filter_map(|line| line.split_once('='))
+                    .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+fn var<'a>(run: &'a HashMap<String, String>, name: &str) -> &'a str {
+    run.get(name).map(String::as_str).unwrap_or_default()
+}
+
+#[test]
+fn lease_bound_changed_unchanged_and_down() {
+    let harness = Harness::new();
+    let mut monitor = harness.monitor(harness.config());
+
+    // No lease file yet
+    assert_eq!(monitor.run_once(), 0);
+    assert!(harness.take_runs().is_empty());
+
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.10\n");
+    assert_eq!(monitor.run_once(), 2);
+    let runs = harness.take_runs();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(var(&runs[0], "DHCP_EVENT"), "bound");
+    assert_eq!(var(&runs[0], "DHCP_IFACE"), "em0");
+    assert_eq!(var(&runs[0], "DHCP_IP_ADDR"), "192.0.2.10");
+    assert_eq!(var(&runs[0], "DHCP_IP_ROUTE"), "192.0.2.1");
+    assert_eq!(var(&runs[0], "DHCP_OLD_IP_ADDR"), "");
+    assert_eq!(var(&runs[0], "DHCP_FAMILY"), "inet");
+
+    // Not modified, then rewritten with the same lease: no trigger
+    assert_eq!(monitor.run_once(), 0);
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.10\n");
+    assert_eq!(monitor.run_once(), 0);
+    assert!(harness.take_runs().is_empty());
+
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.11\n");
+    assert_eq!(monitor.run_once(), 2);
+    let runs = harness.take_runs();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(var(&runs[0], "DHCP_EVENT"), "changed");
+    assert_eq!(var(&runs[0], "DHCP_IP_ADDR"), "192.0.2.11");
+    assert_eq!(var(&runs[0], "DHCP_OLD_IP_ADDR"), "192.0.2.10");
+
+    harness.remove_lease("leases", "em0");
+    assert_eq!(monitor.run_once(), 2);
+    let runs = harness.take_runs();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(var(&runs[0], "DHCP_EVENT"), "down");
+    assert_eq!(var(&runs[0], "DHCP_IP_ADDR"), "");
+    assert_eq!(var(&runs[0], "DHCP_OLD_IP_ADDR"), "192.0.2.11");
+
+    // Nothing left to take down
+    assert_eq!(monitor.run_once(), 0);
+    assert!(harness.take_runs().is_empty());
+}
+
+#[test]
+fn lease_file_modified_back_in_time_is_a_change() {
+    let harness = Harness::new();
+    let mut monitor = harness.monitor(harness.config());
+
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.10\n");
+    assert_eq!(monitor.run_once(), 2);
+    harness.take_runs();
+
+    // Rewritten in place (same inode and size) after the clock was stepped
+    // back, the modification time is older than the one already seen
+    let lease_path = harness.path("leases").join("em0");
+    let modified = fs::metadata(&lease_path).unwrap().modified().unwrap();
+    fs::write(&lease_path, "version: 2\nip: 192.0.2.11\n").unwrap();
+    File::options()
+        .write(true)
+        .open(&lease_path)
+        .unwrap()
+        .set_modified(modified - Duration::from_secs(3600))
+        .unwrap();
+
+    assert_eq!(monitor.run_once(), 2);
+    let runs = harness.take_runs();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(var(&runs[0], "DHCP_EVENT"), "changed");
+    assert_eq!(var(&runs[0], "DHCP_IP_ADDR"), "192.0.2.11");
+}
+
+#[test]
+fn trigger_on_renew_triggers_rewritten_lease() {
+    let harness = Harness::new();
+    let mut monitor = harness.monitor(Config {
+        trigger_on_renew: true,
+        ..harness.config()
+    });
+
+    harness.write_lease("leases", "em0", "ip: 192.0.2.10\n");
+    assert_eq!(monitor.run_once(), 2);
+    harness.write_lease("leases", "em0", "ip: 192.0.2.10\n");
+    assert_eq!(monitor.run_once(), 2);
+    let runs = harness.take_runs();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(var(&runs[1], "DHCP_EVENT"), "renew");
+}
+
+#[test]
+fn incomplete_lease_file_is_skipped_until_rewritten() {
+    let harness = Harness::new();
+    let mut monitor = harness.monitor(harness.config());
+
+    // Lacks the address even after the retries
+    harness.write_lease("leases", "em0", "version: 2\n");
+    assert_eq!(monitor.run_once(), 0);
+    assert_eq!(monitor.run_once(), 0);
+    assert!(harness.take_runs().is_empty());
+
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.10\n");
+    assert_eq!(monitor.run_once(), 2);
+    assert_eq!(var(&harness.take_runs()[0], "DHCP_EVENT"), "bound");
+}
+
+#[test]
+fn invalid_lease_is_not_triggered() {
+    let harness = Harness::new();
+    let mut monitor = harness.monitor(harness.config());
+
+    harness.write_lease("leases", "em0", "ip: 192.0.2.300\n");
+    assert_eq!(monitor.run_once(), 0);
+    assert!(harness.take_runs().is_empty());
+}
+
+#[test]
+fn only_expired_isc_leases_without_previous_lease() {
+    let harness = Harness::new();
+    let mut monitor = harness.monitor(Config {
+        lease_format: LeaseFormat::Isc,
+        ..harness.config()
+    });
+
+    let expired = "lease {\n  interface \"em0\";\n  fixed-address 192.0.2.10;\n  \
+                   expire 4 2020/01/02 00:00:00;\n}\n";
+    harness.write_lease("leases", "em0", expired);
+    assert_eq!(monitor.run_once(), 0);
+    assert!(harness.take_runs().is_empty());
+}
+
+#[test]
+fn list_values_are_joined_by_the_separator() {
+    let harness = Harness::new();
+    let config = Config {
+        ipv6: true,
+        ..harness.config()
+    };
+    harness.write_lease(
+        "leases",
+        "em0",
+        "version: 2\nip: 192.0.2.10\nnameserver: 192.0.2.53\nnameserver: 192.0.2.54\n",
+    );
+    harness.write_lease(
+        "leases6",
+        "em0",
+        "ia_pd 0 2001:db8:1:: 48\nia_pd 1 2001:db8:2:: 56\n\
+         nameserver 2001:db8::53\nnameserver 2001:db8::54\n",
+    );
+
+    // Space-separated by default
+    let mut monitor = harness.monitor(config.clone());
+    assert_eq!(monitor.run_once(), 2);
+    let runs = runs_by_family(&harness);
+    assert_eq!(var(&runs[0], "DHCP_DNS"), "192.0.2.53 192.0.2.54");
+    assert_eq!(var(&runs[1], "DHCP6_DNS"), "2001:db8::53 2001:db8::54");
+    assert_eq!(
+        var(&runs[1], "DHCP6_IP_PREFIXES"),
+        "2001:db8:1::/48 2001:db8:2::/56"
+    );
+
+    let mut monitor = harness.monitor(Config {
+        list_separator: String::from(","),
+        ..config
+    });
+    assert_eq!(monitor.run_once(), 2);
+    let runs = runs_by_family(&harness);
+    assert_eq!(var(&runs[0], "DHCP_DNS"), "192.0.2.53,192.0.2.54");
+    assert_eq!(var(&runs[1], "DHCP6_DNS"), "2001:db8::53,2001:db8::54");
+    assert_eq!(
+        var(&runs[1], "DHCP6_IP_PREFIXES"),
+        "2001:db8:1::/48,2001:db8:2::/56"
+    );
+    // The numbered ones are there all the same
+    assert_eq!(var(&runs[1], "DHCP6_IP_PREFIX_1"), "2001:db8:2::");
+}
+
+#[test]
+fn failed_trigger_marks_the_interface_until_one_succeeds() {
+    let harness = Harness::new();
+    let status_file = harness.path("status");
+    let mut monitor = harness.monitor(Config {
+        status_file: Some(status_file.display().to_string()),
+        ..harness.config()
+    });
+    let failed = || -> serde_json::Value {
+        let status: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&status_file).unwrap()).unwrap();
+        status["interfaces"]["em0"]["failed"].clone()
+    };
+
+    let script_path = harness.path("scripts").join("lease_trigger_em0");
+    fs::write(&script_path, "#!/bin/sh\nexit 1\n").unwrap();
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.10\n");
+    assert_eq!(monitor.run_once(), 1);
+    // Seen by the next scan
+    assert_eq!(failed(), false);
+    monitor.run_once();
+    assert_eq!(failed(), true);
+
+    harness.add_script("lease_trigger_em0");
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.11\n");
+    monitor.run_once();
+    assert_eq!(harness.take_runs().len(), 1);
+    assert_eq!(failed(), true);
+    monitor.run_once();
+    assert_eq!(failed(), false);
+}
+
+#[test]
+fn interface_env_is_added_without_overriding() {
+    let harness = Harness::new();
+    let mut em0 = InterfaceConfig::new("em0");
+    em0.env.insert(String::from("ZONE"), String::from("dmz"));
+    em0.env
+        .insert(String::from("DHCP_IP_ADDR"), String::from("203.0.113.1"));
+    em0.env
+        .insert(String::from("DHCP_ZONE"), String::from("dmz"));
+    let mut monitor = harness.monitor(Config {
+        interfaces: vec![em0],
+        ..harness.config()
+    });
+
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.10\n");
+    assert_eq!(monitor.run_once(), 2);
+    let runs = harness.take_runs();
+    assert_eq!(var(&runs[0], "ZONE"), "dmz");
+    assert_eq!(var(&runs[0], "DHCP_IP_ADDR"), "192.0.2.10");
+    // Not one of the daemon's, but in its namespace
+    assert!(!runs[0].contains_key("DHCP_ZONE"));
+}
+
+#[test]
+fn dry_run_runs_no_hook_scripts() {
+    let harness = Harness::new();
+    let hook_path = harness.path("scripts").join("hook");
+    fs::write(
+        &hook_path,
+        format!(
+            "#!/bin/sh\necho \"$DHCP_HOOK\" >> '{}'\n",
+            harness.path("hooks").display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&hook_path, fs::Permissions::from_mode(0o755)).unwrap();
+    let hook = hook_path.display().to_string();
+    let config = Config {
+        pre_hook: Some(hook.clone()),
+        post_hook: Some(hook.clone()),
+        ..harness.config()
+    };
+
+    // Run as usual
+    let mut monitor = harness.monitor(config.clone());
+    assert!(monitor.run_hook_script(&hook, "init", &[]));
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.10\n");
+    assert_eq!(monitor.run_once(), 2);
+    assert_eq!(
+        fs::read_to_string(harness.path("hooks")).unwrap(),
+        "init\npre\npost\n"
+    );
+    fs::remove_file(harness.path("hooks")).unwrap();
+    harness.take_runs();
+
+    let mut monitor = harness.monitor(Config {
+        dry_run: true,
+        ..config
+    });
+    assert!(monitor.run_hook_script(&hook, "init", &[]));
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.11\n");
+    assert_eq!(monitor.run_once(), 2);
+    assert!(monitor.run_hook_script(&hook, "cleanup", &[]));
+    assert!(!harness.path("hooks").exists());
+    assert!(harness.take_runs().is_empty());
+}
+
+/// The runs of one scan by family, IPv4 first (the scripts run concurrently)
+fn runs_by_family(harness: &Harness) -> Vec<HashMap<String, String>> {
+    let mut runs = harness.take_runs();
+    runs.sort_by(|a, b| var(a, "DHCP_FAMILY").cmp(var(b, "DHCP_FAMILY")));
+    runs
+}
+
+#[test]
+fn dual_stack_runs_the_script_of_each_family() {
+    let harness = Harness::new();
+    let config = Config {
+        ipv6: true,
+        ..harness.config()
+    };
+    let mut monitor = harness.monitor(config.clone());
+
+    // The same script by default, told apart by DHCP_FAMILY
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.10\n");
+    harness.write_lease("leases6", "em0", "ia_pd 0 2001:db8:1:: 48\n");
+    assert_eq!(monitor.run_once(), 2);
+    let runs = runs_by_family(&harness);
+    assert_eq!(runs.len(), 2);
+    assert_eq!(var(&runs[0], "SCRIPT"), "lease_trigger_em0");
+    assert_eq!(var(&runs[0], "DHCP_FAMILY"), "inet");
+    assert_eq!(var(&runs[0], "DHCP_IP_ADDR"), "192.0.2.10");
+    assert!(!runs[0].contains_key("DHCP6_IP_PREFIX"));
+    assert_eq!(var(&runs[1], "SCRIPT"), "lease_trigger_em0");
+    assert_eq!(var(&runs[1], "DHCP_FAMILY"), "inet6");
+    assert_eq!(var(&runs[1], "DHCP6_IP_PREFIX"), "2001:db8:1::");
+    assert!(!runs[1].contains_key("DHCP_IP_ADDR"));
+
+    // A distinct IPv6 prefix, the IPv4 script is left alone
+    harness.add_script("lease6_trigger_em0");
+    let mut monitor = harness.monitor(Config {
+        trigger_script_prefix_ipv6: "lease6_trigger_".to_string(),
+        ..config.clone()
+    });
+    assert_eq!(monitor.run_once(), 2);
+    let runs = runs_by_family(&harness);
+    assert_eq!(runs.len(), 2);
+    assert_eq!(var(&runs[0], "SCRIPT"), "lease_trigger_em0");
+    assert_eq!(var(&runs[0], "DHCP_FAMILY"), "inet");
+    assert_eq!(var(&runs[1], "SCRIPT"), "lease6_trigger_em0");
+    assert_eq!(var(&runs[1], "DHCP_FAMILY"), "inet6");
+
+    // The per-interface scripts win over the prefixes
+    harness.add_script("em0-v4");
+    harness.add_script("em0-v6");
+    let mut monitor = harness.monitor(Config {
+        interfaces: vec![InterfaceConfig {
+            trigger_script: Some("em0-v4".to_string()),
+            trigger_script_ipv6: Some("em0-v6".to_string()),
+            ..InterfaceConfig::new("em0")
+        }],
+        ..config
+    });
+    assert_eq!(monitor.run_once(), 2);
+    let runs = runs_by_family(&harness);
+    assert_eq!(runs.len(), 2);
+    assert_eq!(var(&runs[0], "SCRIPT"), "em0-v4");
+    assert_eq!(var(&runs[0], "DHCP_FAMILY"), "inet");
+    assert_eq!(var(&runs[1], "SCRIPT"), "em0-v6");
+    assert_eq!(var(&runs[1], "DHCP_FAMILY"), "inet6");
+}
+
+#[test]
+fn unified_trigger_json_stdin_carries_both_families() {
+    let harness = Harness::new();
+    let script_path = harness.path("scripts").join("lease_trigger_em0");
+    fs::write(
+        &script_path,
+        format!("#!/bin/sh\ncat >> '{}'\n", harness.path("stdin").display()),
+    )
+    .unwrap();
+    let mut monitor = harness.monitor(Config {
+        ipv6: true,
+        unified_trigger: true,
+        json_stdin: true,
+        ..harness.config()
+    });
+
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.10\n");
+    harness.write_lease("leases6", "em0", "ia_pd 0 2001:db8:1:: 48\n");
+    assert_eq!(monitor.run_once(), 2);
+
+    // Run once, for both families
+    let stdin = fs::read_to_string(harness.path("stdin")).unwrap();
+    assert_eq!(stdin.lines().count(), 1);
+    let json: serde_json::Value = serde_json::from_str(&stdin).unwrap();
+    assert_eq!(json["iface"], "em0");
+    assert_eq!(json["stack"], "dual");
+    assert_eq!(
+        json["changed_families"],
+        serde_json::json!(["inet", "inet6"])
+    );
+    assert_eq!(json["inet"]["event"], "bound");
+    assert_eq!(json["inet"]["lease"]["ip_addr"], "192.0.2.10");
+    assert!(json["inet"]["old_lease"].is_null());
+    assert_eq!(json["inet6"]["event"], "bound");
+    assert_eq!(json["inet6"]["lease"]["ip6_prefix"], "2001:db8:1::");
+    assert!(json["inet6"]["old_lease"].is_null());
+}
+
+/// Sorted names of the entries in a directory
+fn dir_entries(dir: &PathBuf) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn read_only_lease_dirs_are_only_read() {
+    let harness = Harness::new();
+    let config = Config {
+        ipv6: true,
+        state_file: Some(harness.path("state").to_string_lossy().into_owned()),
+        status_file: Some(harness.path("status").to_string_lossy().into_owned()),
+        ..harness.config()
+    };
+    let mut monitor = harness.monitor(config.clone());
+    assert_eq!(config.lease_dirs().len(), 2);
+
+    harness.write_lease("leases", "em0", "version: 2\nip: 192.0.2.10\n");
+    harness.write_lease("leases6", "em0", "ia_pd 0 2001:db8:1:: 48\n");
+    for dir in ["leases", "leases6"] {
+        fs::set_permissions(harness.path(dir), fs::Permissions::from_mode(0o555)).unwrap();
+    }
+
+    assert!(monitor.check_lease_dirs());
+    assert_eq!(monitor.run_once(), 2);
+    assert_eq!(harness.take_runs().len(), 2);
+    assert_eq!(monitor.run_once(), 0);
+
+    // Tests may run as root, which writes to read-only directories anyway:
+    // nothing but the lease files is there
+    assert_eq!(dir_entries(&harness.path("leases")), vec!["em0"]);
+    assert_eq!(dir_entries(&harness.path("leases6")), vec!["em0"]);
+    assert!(harness.path("state").exists());
+    assert!(harness.path("status").exists());
+
+    // The state is read back from outside the lease directories
+    let mut monitor = harness.monitor(config);
+    monitor.load_state();
+    assert_eq!(monitor.run_once(), 0);
+    assert!(harness.take_runs().is_empty());
+}
+
+#[test]
+fn ipv6_lease_runs_the_ipv6_script() {
+    let harness = Harness::new();
+    let mut monitor = harness.monitor(Config {
+        ipv6: true,
+        ..harness.config()
+    });
+
+    harness.write_lease("leases6", "em0", "ia_pd 0 2001:db8:1:: 48\n");
+    assert_eq!(monitor.run_once(), 2);
+    let runs = harness.take_runs();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(var(&runs[0], "DHCP_FAMILY"), "inet6");
+    assert_eq!(var(&runs[0], "DHCP6_IP_PREFIX"), "2001:db8:1::");
+    assert_eq!(var(&runs[0], "DHCP6_IP_PREFIX_LEN"), "48");
+    assert_eq!(var(&runs[0], "DHCP6_IP_ROUTE"), "fe80::1%em0");
+}
+
+/// Runs a monitor with the state file until the lease is recorded in it
+fn record_state(harness: &Harness, config: &Config) {
+    let mut monitor = harness.monitor(config.clone());
+    monitor.load_state();
+    monitor.run_once();
+    harness.take_runs();
+}
+
+#[test]
+fn startup_triggers_every_lease_without_state_file() {
+    let harness = Harness::new();
+    harness.write_lease("leases", "em0", "ip: 192.0.2.10\n");
+
+    let mut monitor = harness.monitor(harness.config());
+    monitor.load_state();
+    assert_eq!(monitor.run_once(), 2);
+    assert_eq!(var(&harness.take_runs()[0], "DHCP_EVENT"), "bound");
+}
+
+#[test]
+fn startup_triggers_only_leases_differing_from_state_file() {
+    let harness = Harness::new();
+    let config = Config {
+        state_file: Some(harness.path("state.json").display().to_string()),
+        ..harness.config()
+    };
+    harness.write_lease("leases", "em0", "ip: 192.0.2.10\n");
+    record_state(&harness, &config);
+
+    // Restarted with the same lease
+    let mut monitor = harness.monitor(config.clone());
+    monitor.load_state();
+    assert_eq!(monitor.run_once(), 0);
+    assert!(harness.take_runs().is_empty());
+
+    // Restarted after the lease changed while the daemon was down
+    harness.write_lease("leases", "em0", "ip: 192.0.2.11\n");
+    let mut monitor = harness.monitor(config);
+    monitor.load_state();
+    assert_eq!(monitor.run_once(), 2);
+    let runs = harness.take_runs();
+    assert_eq!(var(&runs[0], "DHCP_EVENT"), "changed");
+    assert_eq!(var(&runs[0], "DHCP_OLD_IP_ADDR"), "192.0.2.10");
+}
+
+#[test]
+fn no_trigger_on_startup_records_differing_leases() {
+    let harness = Harness::new();
+    let config = Config {
+        state_file: Some(harness.path("state.json").display().to_string()),
+        ..harness.config()
+    };
+    harness.write_lease("leases", "em0", "ip: 192.0.2.10\n");
+    record_state(&harness, &config);
+
+    harness.write_lease("leases", "em0", "ip: 192.0.2.11\n");
+    let config = Config {
+        trigger_on_startup: false,
+        ..config
+    };
+    let mut monitor = harness.monitor(config.clone());
+    monitor.load_state();
+    assert_eq!(monitor.run_once(), 0);
+    assert!(harness.take_runs().is_empty());
+
+    // Recorded: the next change is relative to it
+    harness.write_lease("leases", "em0", "ip: 192.0.2.12\n");
+    assert_eq!(monitor.run_once(), 2);
+    assert_eq!(
+        var(&harness.take_runs()[0], "DHCP_OLD_IP_ADDR"),
+        "192.0.2.11"
+    );
+
+    // Gone while the daemon was down: dropped, not taken down
+    harness.remove_lease("leases", "em0");
+    let mut monitor = harness.monitor(config);
+    monitor.load_state();
+    assert_eq!(monitor.run_once(), 0);
+    assert_eq!(monitor.run_once(), 0);
+    assert!(harness.take_runs().is_empty());
+    let state = fs::read_to_string(harness.path("state.json")).unwrap();
+    assert!(!state.contains("em0"), "{}", state);
+}
+
+#[test]
+fn lease_file_written_during_scans_is_read_complete() {
+    let harness = Harness::new();
+    let mut monitor = harness.monitor(harness.config());
+    let lease_file_path = harness.path("leases").join("em0");
+    fs::write(&lease_file_path, "version: 2\nip: 192.0.2.10\n").unwrap();
+
+    // Rewrites the lease file in place (truncated, then written in two
+    // writes): meanwhile it is empty or lacks the address. Returns the
+    // address written last.
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            let mut ip_addr = "192.0.2.10";
+            while !stop.load(Ordering::Relaxed) {
+                ip_addr = if ip_addr == "192.0.2.10" {
+                    "192.0.2.11"
+                } else {
+                    "192.0.2.10"
+                };
+                let mut file = File::create(&lease_file_path).unwrap();
+                file.write_all(b"version: 2\n").unwrap();
+                thread::sleep(Duration::from_millis(20));
+                writeln!(file, "ip: {}", ip_addr).unwrap();
+                drop(file);
+                thread::sleep(Duration::from_millis(30));
+            }
+            ip_addr
+        })
+    };
+
+    // The scans never take an incomplete lease file for a lost lease, nor
+    // trigger a lease that was not written
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while Instant::now() < deadline {
+        assert_ne!(monitor.run_once(), 1);
+        thread::sleep(Duration::from_millis(10));
+    }
+    stop.store(true, Ordering::Relaxed);
+    let last_ip_addr = writer.join().unwrap();
+    monitor.run_once();
+
+    let runs = harness.take_runs();
+    assert!(runs.len() > 1);
+    for run in &runs {
+        assert_ne!(var(run, "DHCP_EVENT"), "down");
+        assert!(
+            ["192.0.2.10", "192.0.2.11"].contains(&var(run, "DHCP_IP_ADDR")),
+            "{:?}",
+            run
+        );
+    }
+    // In the end the lease written last is the one triggered last
+    assert_eq!(var(runs.last().unwrap(), "DHCP_IP_ADDR"), last_ip_addr);
+}
+
+#[test]
+fn ipv6_lease_exports_every_prefix() {
+    let harness = Harness::new();
+    let mut monitor = harness.monitor(Config {
+        ipv6: true,
+        ..harness.config()
+    });
+
+    // None delegated, only an address
+    harness.write_lease("leases6", "em0", "ia_na 0 2001:db8::10\n");
+    assert_eq!(monitor.run_once(), 2);
+    let run = &harness.take_runs()[0];
+    assert_eq!(var(run, "DHCP6_IP_PREFIX_COUNT"), "0");
+    assert_eq!(var(run, "DHCP6_IP_PREFIX"), "");
+    assert!(!run.contains_key("DHCP6_IP_PREFIX_0"));
+    assert_eq!(var(run, "DHCP6_IP_ADDR"), "2001:db8::10");
+
+    harness.write_lease("leases6", "em0", "ia_pd 0 2001:db8:1:: 48\n");
+    assert_eq!(monitor.run_once(), 2);
+    let run = &harness.take_runs()[0];
+    assert_eq!(var(run, "DHCP6_IP_PREFIX_COUNT"), "1");
+    assert_eq!(var(run, "DHCP6_IP_PREFIX_0"), "2001:db8:1::");
+    assert_eq!(var(run, "DHCP6_IP_PREFIX_LEN_0"), "48");
+    assert!(!run.contains_key("DHCP6_IP_PREFIX_1"));
+
+    harness.write_lease(
+        "leases6",
+        "em0",
+        "ia_pd 0 2001:db8:1:: 48\nia_pd 1 2001:db8:2:: 56\nia_pd 2 2001:db8:3:: 64\n",
+    );
+    assert_eq!(monitor.run_once(), 2);
+    let run = &harness.take_runs()[0];
+    assert_eq!(var(run, "DHCP6_IP_PREFIX_COUNT"), "3");
+    // The first one is also the single-prefix one
+    assert_eq!(var(run, "DHCP6_IP_PREFIX"), "2001:db8:1::");
+    assert_eq!(var(run, "DHCP6_IP_PREFIX_LEN"), "48");
+    assert_eq!(var(run, "DHCP6_IP_PREFIX_1"), "2001:db8:2::");
+    assert_eq!(var(run, "DHCP6_IP_PREFIX_LEN_1"), "56");
+    assert_eq!(var(run, "DHCP6_IP_PREFIX_2"), "2001:db8:3::");
+    assert_eq!(var(run, "DHCP6_IP_PREFIX_LEN_2"), "64");
+}
+
+// EOF